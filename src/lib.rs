@@ -1,5 +1,3 @@
-#![feature(let_chains, box_patterns)]
-
 pub mod umpteen {
     pub mod error;
     pub mod exec;
@@ -11,13 +9,17 @@ pub(crate) use umpteen::util;
 pub use umpteen::{error, exec, repr};
 
 use rustyline::error::ReadlineError;
-use umpteen::{error::UmpteenError, exec::interpreter::Interpreter, repr::value::Value};
+use umpteen::{
+    error::UmpteenError,
+    exec::interpreter::{Backend, Interpreter},
+    repr::value::Value,
+};
 
-pub fn repl() {
+pub fn repl(backend: Backend) {
     let mut rl = rustyline::DefaultEditor::new().unwrap();
     let _ = rl.load_history("umpteen_history");
 
-    let mut umpteen = Interpreter::new();
+    let mut umpteen = Interpreter::with_backend(backend);
     prompt();
 
     let mut interrupt = false;
@@ -50,7 +52,11 @@ pub fn repl() {
     let _ = rl.save_history("umpteen_history");
 }
 
-pub fn run_file(path: &str) {
+pub fn run_file(path: &str, backend: Backend) {
+    if path.ends_with(".umc") {
+        return run_compiled(path);
+    }
+
     let code = match std::fs::read_to_string(path) {
         Ok(code) => code,
         Err(err) => {
@@ -59,10 +65,48 @@ pub fn run_file(path: &str) {
         }
     };
 
-    let mut umpteen = Interpreter::new();
+    let mut umpteen = Interpreter::with_backend(backend);
     handle(umpteen.run(&code));
 }
 
+pub fn compile_file(path: &str, output: Option<&str>) {
+    let code = match std::fs::read_to_string(path) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+
+    let output = output.map_or_else(
+        || std::path::Path::new(path).with_extension("umc"),
+        std::path::PathBuf::from,
+    );
+
+    let mut umpteen = Interpreter::with_backend(Backend::Bytecode);
+    match umpteen.build(&code) {
+        Ok(bytes) => {
+            if let Err(err) = std::fs::write(output, bytes) {
+                eprintln!("{}", err);
+            }
+        }
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+fn run_compiled(path: &str) {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+
+    let mut umpteen = Interpreter::with_backend(Backend::Bytecode);
+    handle(umpteen.run_compiled(&bytes));
+}
+
 fn prompt() {
     let version = env!("CARGO_PKG_VERSION");
     println!("Umpteen v{} — 2023", version);
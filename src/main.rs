@@ -1,11 +1,46 @@
-use umpteen::{repl, run_file};
+use umpteen::{compile_file, exec::interpreter::Backend, repl, run_file};
+
+// Tree-walked calls nest on the native stack, which must have room for the call depth limit
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let cli = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(cli)
+        .expect("failed to start the interpreter thread");
+    // A panic has already been reported by the time it is joined
+    if cli.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn cli() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().is_some_and(|arg| arg == "compile") {
+        let Some(path) = args.get(1) else {
+            eprintln!("usage: umpteen compile <file> [-o <output>]");
+            return;
+        };
+        let output = args
+            .iter()
+            .position(|arg| arg == "-o")
+            .and_then(|i| args.get(i + 1));
+
+        compile_file(path, output.map(String::as_str));
+        return;
+    }
+
+    let backend = if args.iter().any(|arg| arg == "--vm") {
+        Backend::Bytecode
+    } else {
+        Backend::TreeWalk
+    };
+    let path = args.iter().find(|arg| !arg.starts_with("--"));
 
-    if args.len() > 1 {
-        run_file(&args[1]);
+    if let Some(path) = path {
+        run_file(path, backend);
     } else {
-        repl();
+        repl(backend);
     }
 }
@@ -1,9 +1,17 @@
+mod compiler_error;
+mod format_error;
 mod interpret_error;
+mod lex_error;
 mod memory_error;
 mod parse_error;
+mod resolve_error;
 mod umpteen_error;
 
+pub use compiler_error::*;
+pub use format_error::*;
 pub use interpret_error::*;
+pub use lex_error::*;
 pub use memory_error::*;
 pub use parse_error::*;
+pub use resolve_error::*;
 pub use umpteen_error::*;
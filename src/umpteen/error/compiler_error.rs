@@ -0,0 +1,42 @@
+use std::{error::Error, fmt::Display};
+
+use super::MemoryError;
+
+#[derive(Debug)]
+pub enum CompilerError {
+    MemoryError(MemoryError),
+    AddressOutOfRange(usize),
+    InvalidAddressWidth(usize),
+    JumpTooLarge(usize),
+    TooManyArguments(usize),
+    InvalidOpCode(u8),
+    IllegalDivergence(String),
+}
+
+impl Display for CompilerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            CompilerError::MemoryError(e) => e.to_string(),
+            CompilerError::AddressOutOfRange(x) => format!("address {} is out of range", x),
+            CompilerError::InvalidAddressWidth(x) => {
+                format!("invalid address width of {} bytes", x)
+            }
+            CompilerError::JumpTooLarge(x) => format!("cannot jump over {} bytes of code", x),
+            CompilerError::TooManyArguments(x) => {
+                format!("too many arguments ({}), limit is {}", x, u8::MAX)
+            }
+            CompilerError::InvalidOpCode(x) => format!("invalid opcode {:#04x}", x),
+            CompilerError::IllegalDivergence(x) => format!("illegal divergence: {}", x),
+        };
+
+        write!(f, "{}", desc)
+    }
+}
+
+impl From<MemoryError> for CompilerError {
+    fn from(value: MemoryError) -> Self {
+        CompilerError::MemoryError(value)
+    }
+}
+
+impl Error for CompilerError {}
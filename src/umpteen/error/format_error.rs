@@ -0,0 +1,34 @@
+use std::{error::Error, fmt::Display};
+
+#[derive(Debug)]
+pub enum FormatError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnexpectedEof,
+    InvalidTag(u8),
+    InvalidUtf8,
+    UnknownNative(String),
+    Unserializable(String),
+    InvalidBytecode(String),
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            FormatError::BadMagic => "not an Umpteen bytecode file".to_string(),
+            FormatError::UnsupportedVersion(x) => {
+                format!("unsupported bytecode version {}", x)
+            }
+            FormatError::UnexpectedEof => "unexpected end of bytecode file".to_string(),
+            FormatError::InvalidTag(x) => format!("invalid constant tag {:#04x}", x),
+            FormatError::InvalidUtf8 => "invalid UTF-8 in bytecode string".to_string(),
+            FormatError::UnknownNative(x) => format!("unknown native function `{}`", x),
+            FormatError::Unserializable(x) => format!("cannot serialize constant {}", x),
+            FormatError::InvalidBytecode(x) => format!("invalid bytecode: {}", x),
+        };
+
+        write!(f, "{}", desc)
+    }
+}
+
+impl Error for FormatError {}
@@ -1,6 +1,9 @@
 use std::{error::Error, fmt::Display};
 
-use crate::exec::interpreter::Divergence;
+use crate::{
+    exec::interpreter::Divergence,
+    repr::{fnc::Arity, types::Type},
+};
 
 use super::MemoryError;
 
@@ -9,6 +12,42 @@ pub enum InterpretError {
     MemoryError(MemoryError),
     IllegalDivergence(String),
     TriedToCallNonFunction(String),
+    NotIterable(Type),
+    InvalidRange(String),
+    NoSuchField {
+        ty: Type,
+        field: String,
+    },
+    NoSuchVariant {
+        ty: String,
+        variant: String,
+    },
+    NoMatch(String),
+    FieldTypeMismatch {
+        record: String,
+        field: String,
+        expected: Type,
+        got: Type,
+    },
+    ArityMismatch {
+        name: String,
+        expected: Arity,
+        got: usize,
+    },
+    ArgumentTypeMismatch {
+        fnc: String,
+        param: String,
+        expected: Type,
+        got: Type,
+    },
+    ReturnTypeMismatch {
+        fnc: String,
+        expected: Type,
+        got: Type,
+    },
+    InvalidCodePoint(f64),
+    NotACharacter(String),
+    CallDepthExceeded(usize),
 }
 
 impl Display for InterpretError {
@@ -17,6 +56,56 @@ impl Display for InterpretError {
             InterpretError::MemoryError(e) => e.to_string(),
             InterpretError::IllegalDivergence(x) => format!("illegal divergence: {}", x),
             InterpretError::TriedToCallNonFunction(x) => format!("`{}` is not a function", x),
+            InterpretError::NotIterable(ty) => format!("cannot iterate over {}", ty),
+            InterpretError::InvalidRange(x) => format!("invalid range: {}", x),
+            InterpretError::NoSuchField { ty, field } => format!("{} has no field `{}`", ty, field),
+            InterpretError::NoSuchVariant { ty, variant } => {
+                format!("`{}` has no variant `{}`", ty, variant)
+            }
+            InterpretError::NoMatch(x) => format!("no arm of the match accepts {}", x),
+            InterpretError::FieldTypeMismatch {
+                record,
+                field,
+                expected,
+                got,
+            } => format!(
+                "field `{}` of `{}` expects {}, got {}",
+                field, record, expected, got
+            ),
+            InterpretError::ArityMismatch {
+                name,
+                expected,
+                got,
+            } => {
+                let plural = if *expected == Arity::fixed(1) {
+                    ""
+                } else {
+                    "s"
+                };
+                format!(
+                    "`{}` expects {} argument{}, got {}",
+                    name, expected, plural, got
+                )
+            }
+            InterpretError::ArgumentTypeMismatch {
+                fnc,
+                param,
+                expected,
+                got,
+            } => format!(
+                "parameter `{}` of `{}` expects {}, got {}",
+                param, fnc, expected, got
+            ),
+            InterpretError::ReturnTypeMismatch { fnc, expected, got } => {
+                format!("`{}` should return {}, returned {}", fnc, expected, got)
+            }
+            InterpretError::InvalidCodePoint(x) => format!("{} is not a Unicode code point", x),
+            InterpretError::NotACharacter(x) => {
+                format!("expected a single character, got \"{}\"", x)
+            }
+            InterpretError::CallDepthExceeded(x) => {
+                format!("calls nested more than {} deep", x)
+            }
         };
 
         write!(f, "{}", desc)
@@ -0,0 +1,44 @@
+use std::{error::Error, fmt::Display};
+
+#[derive(Debug)]
+pub enum LexError {
+    UnexpectedSymbol(char),
+    UnterminatedString,
+    UnmatchedBrace,
+    EmptyInterpolation,
+    InvalidEscape(String),
+    InvalidDigit(char, u32),
+    MissingDigits(String),
+    MisplacedSeparator,
+    NumberOutOfRange(String),
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            LexError::UnexpectedSymbol(c) => format!("unexpected symbol `{}`", c),
+            LexError::UnterminatedString => "unterminated string literal".to_string(),
+            LexError::UnmatchedBrace => {
+                "unmatched `}` in string literal, write `}}` for a literal brace".to_string()
+            }
+            LexError::EmptyInterpolation => "empty interpolation".to_string(),
+            LexError::InvalidEscape(x) => format!("invalid escape sequence `{}`", x),
+            LexError::InvalidDigit(c, radix) => {
+                let base = match radix {
+                    2 => "binary",
+                    8 => "octal",
+                    16 => "hexadecimal",
+                    _ => "decimal",
+                };
+                format!("invalid digit `{}` in {} literal", c, base)
+            }
+            LexError::MissingDigits(x) => format!("expected digits after `{}`", x),
+            LexError::MisplacedSeparator => "`_` may only separate digits".to_string(),
+            LexError::NumberOutOfRange(x) => format!("numeric literal `{}` is out of range", x),
+        };
+
+        write!(f, "{}", desc)
+    }
+}
+
+impl Error for LexError {}
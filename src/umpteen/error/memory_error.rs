@@ -7,6 +7,14 @@ pub enum MemoryError {
     OutOfBoundsMemoryAccess,
     CannotIndex(String),
     CannotIndexWith(String),
+    InvalidReference(usize),
+    AlreadyDeclared(String),
+    AssignToImmutable(String),
+    UnhashableKey(String),
+    NoSuchKey(String),
+    IndexOutOfBounds { index: f64, len: usize },
+    NonIntegerIndex(f64),
+    ZeroSliceStep,
 }
 
 impl Display for MemoryError {
@@ -15,8 +23,20 @@ impl Display for MemoryError {
             MemoryError::NoSuchVariable(x) => format!("no such variable `{}`", x),
             MemoryError::OutOfBoundsMemoryAccess => "out of bounds memory access".to_string(),
             MemoryError::CannotIndex(x) => format!("cannot index into {}", x),
-            MemoryError::CannotIndexWith(x) => format!("invalid index type for variable `{}`", x),
+            MemoryError::CannotIndexWith(x) => format!("invalid index type for {}", x),
             MemoryError::UninitializedVariable(x) => format!("variable `{}` is not initialized", x),
+            MemoryError::InvalidReference(x) => format!("invalid reference to address {:#06x}", x),
+            MemoryError::AlreadyDeclared(x) => format!("variable `{}` is already declared", x),
+            MemoryError::AssignToImmutable(x) => {
+                format!("cannot assign to immutable variable `{}`", x)
+            }
+            MemoryError::UnhashableKey(x) => format!("cannot use {} as a map key", x),
+            MemoryError::NoSuchKey(x) => format!("no such key `{}`", x),
+            MemoryError::IndexOutOfBounds { index, len } => {
+                format!("index {} is out of bounds for length {}", index, len)
+            }
+            MemoryError::NonIntegerIndex(x) => format!("index {} is not a whole number", x),
+            MemoryError::ZeroSliceStep => "slice step cannot be zero".to_string(),
         };
         write!(f, "{}", desc)
     }
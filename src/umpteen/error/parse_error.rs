@@ -16,6 +16,14 @@ pub enum ParseError {
     UnexpectedToken(TokenType),
     ExpectedToken(TokenType),
     InvalidAssignmentTarget(String),
+    UnknownType(String),
+    LowercaseTypeName(String),
+    DuplicateField(String),
+    DuplicateBinding(String),
+    InvalidPattern(String),
+    RequiredAfterOptional(String),
+    MissingReceiver(String),
+    InvalidFormatSpec(String),
 }
 
 impl Display for ParseError {
@@ -38,6 +46,22 @@ impl Display for ParseError {
             ParseError::UnexpectedToken(kind) => format!("unexpected token: {}", kind),
             ParseError::InvalidNumericLiteral(e) => e.to_string(),
             ParseError::InvalidAssignmentTarget(x) => format!("invalid assignment target `{}`", x),
+            ParseError::UnknownType(x) => format!("unknown type `{}`", x),
+            ParseError::LowercaseTypeName(x) => {
+                format!("type name `{}` must start with a capital letter", x)
+            }
+            ParseError::DuplicateField(x) => {
+                format!("`{}` is declared more than once in the same type", x)
+            }
+            ParseError::DuplicateBinding(x) => format!("`{}` is bound more than once", x),
+            ParseError::InvalidPattern(x) => format!("invalid pattern: {}", x),
+            ParseError::RequiredAfterOptional(x) => {
+                format!("required parameter `{}` follows an optional parameter", x)
+            }
+            ParseError::MissingReceiver(x) => {
+                format!("method `{}` must take `self` as its first parameter", x)
+            }
+            ParseError::InvalidFormatSpec(x) => format!("invalid format spec `{}`", x),
         };
         write!(f, "{}", desc)
     }
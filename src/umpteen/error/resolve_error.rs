@@ -0,0 +1,51 @@
+use std::{error::Error, fmt::Display};
+
+use crate::exec::interpreter::Divergence;
+
+#[derive(Debug)]
+pub enum ResolveError {
+    UseBeforeDeclaration(String),
+    IllegalDivergence(String),
+    NoSuchVariant(String),
+    VariantArity {
+        variant: String,
+        expected: usize,
+        got: usize,
+    },
+    NonExhaustiveMatch(Vec<String>),
+    UnknownType(String),
+}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            ResolveError::UseBeforeDeclaration(x) => {
+                format!("variable `{}` is used before it is declared", x)
+            }
+            ResolveError::IllegalDivergence(x) => format!("illegal divergence: {}", x),
+            ResolveError::NoSuchVariant(x) => format!("no enum variant `{}`", x),
+            ResolveError::VariantArity {
+                variant,
+                expected,
+                got,
+            } => format!(
+                "`{}` holds {} value(s), but the pattern has {}",
+                variant, expected, got
+            ),
+            ResolveError::NonExhaustiveMatch(missing) => {
+                format!("match does not cover {}", missing.join(", "))
+            }
+            ResolveError::UnknownType(x) => format!("unknown type `{}`", x),
+        };
+
+        write!(f, "{}", desc)
+    }
+}
+
+impl From<Divergence> for ResolveError {
+    fn from(value: Divergence) -> Self {
+        ResolveError::IllegalDivergence(value.to_string())
+    }
+}
+
+impl Error for ResolveError {}
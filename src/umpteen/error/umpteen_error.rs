@@ -1,6 +1,6 @@
-use crate::exec::interpreter::Divergence;
-
-use super::{InterpretError, MemoryError, ParseError};
+use super::{
+    CompilerError, FormatError, InterpretError, LexError, MemoryError, ParseError, ResolveError,
+};
 use std::{
     error::Error,
     fmt::{Debug, Display},
@@ -10,26 +10,44 @@ use rustyline::error::ReadlineError;
 
 #[derive(Debug)]
 pub enum UmpteenError {
+    LexError(LexError),
     ParseError(ParseError),
+    ResolveError(ResolveError),
+    CompilerError(CompilerError),
     InterpretError(InterpretError),
     MemoryError(MemoryError),
+    FormatError(FormatError),
     ReplError(ReadlineError),
-    Divergence(Divergence),
+    Located(Box<UmpteenError>, Line),
 }
 
 impl Display for UmpteenError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
+            UmpteenError::LexError(e) => write!(f, "{}", e),
             UmpteenError::ParseError(e) => write!(f, "{}", e),
+            UmpteenError::ResolveError(e) => write!(f, "{}", e),
+            UmpteenError::CompilerError(e) => write!(f, "{}", e),
             UmpteenError::InterpretError(e) => write!(f, "{}", e),
             UmpteenError::MemoryError(e) => write!(f, "{}", e),
+            UmpteenError::FormatError(e) => write!(f, "{}", e),
             UmpteenError::ReplError(e) => write!(f, "{}", e),
-            UmpteenError::Divergence(e) => write!(f, "{}", e),
+            UmpteenError::Located(e, line) => write!(f, "{} on line {}", e, line),
+        }
+    }
+}
+
+impl UmpteenError {
+    /// Places the error on `line`, unless it already carries the deeper location it came from
+    pub fn located(self, line: Line) -> Self {
+        match self {
+            UmpteenError::Located(..) => self,
+            e => UmpteenError::Located(Box::new(e), line),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Line(usize, usize);
 
 impl Line {
@@ -37,6 +55,18 @@ impl Line {
         Self(ln, 0)
     }
 
+    pub fn at(ln: usize, col: usize) -> Self {
+        Self(ln, col)
+    }
+
+    pub fn line(&self) -> usize {
+        self.0
+    }
+
+    pub fn column(&self) -> usize {
+        self.1
+    }
+
     pub fn advance(&mut self) -> Self {
         self.1 += 1;
         *self
@@ -44,7 +74,7 @@ impl Line {
 
     pub fn newline(&mut self) -> Self {
         self.0 += 1;
-        self.1 = 1;
+        self.1 = 0;
         *self
     }
 }
@@ -59,12 +89,30 @@ impl Display for Line {
     }
 }
 
+impl From<LexError> for UmpteenError {
+    fn from(value: LexError) -> Self {
+        UmpteenError::LexError(value)
+    }
+}
+
 impl From<ParseError> for UmpteenError {
     fn from(value: ParseError) -> Self {
         UmpteenError::ParseError(value)
     }
 }
 
+impl From<ResolveError> for UmpteenError {
+    fn from(value: ResolveError) -> Self {
+        UmpteenError::ResolveError(value)
+    }
+}
+
+impl From<CompilerError> for UmpteenError {
+    fn from(value: CompilerError) -> Self {
+        UmpteenError::CompilerError(value)
+    }
+}
+
 impl From<InterpretError> for UmpteenError {
     fn from(value: InterpretError) -> Self {
         UmpteenError::InterpretError(value)
@@ -77,15 +125,15 @@ impl From<MemoryError> for UmpteenError {
     }
 }
 
-impl From<ReadlineError> for UmpteenError {
-    fn from(value: ReadlineError) -> Self {
-        UmpteenError::ReplError(value)
+impl From<FormatError> for UmpteenError {
+    fn from(value: FormatError) -> Self {
+        UmpteenError::FormatError(value)
     }
 }
 
-impl From<Divergence> for UmpteenError {
-    fn from(value: Divergence) -> Self {
-        UmpteenError::Divergence(value)
+impl From<ReadlineError> for UmpteenError {
+    fn from(value: ReadlineError) -> Self {
+        UmpteenError::ReplError(value)
     }
 }
 
@@ -1,4 +1,10 @@
+pub mod compiler;
 pub mod env;
+pub mod heap;
 pub mod interpreter;
 pub mod lexer;
+pub mod memory;
 pub mod parse;
+pub mod resolver;
+pub mod serialize;
+pub mod vm;
@@ -0,0 +1,753 @@
+use crate::{
+    boxed,
+    error::{CompilerError, MemoryError},
+    repr::{
+        ast::{
+            expr::{Expr, Segment},
+            ops::{Binary, Unary},
+            stmt::{Param, Stmt, StmtKind},
+        },
+        chunk::{Chunk, OpCode, Prototype},
+        enumeration::EnumType,
+        fnc::Closure,
+        record::RecordType,
+        types::Type,
+        value::Value,
+    },
+};
+
+use super::{interpreter::Divergence, memory::Memory, parse::Ast};
+
+struct Local {
+    name: String,
+    depth: usize,
+    slot: usize,
+    mutable: bool,
+}
+
+struct LoopState {
+    start: usize,
+    breaks: Vec<usize>,
+    locals: usize, // Locals in scope where the loop starts, which leaving an iteration keeps
+}
+
+struct FncState {
+    proto: Prototype,
+    locals: Vec<Local>,
+    loops: Vec<LoopState>,
+    depth: usize,
+}
+
+impl FncState {
+    fn new(name: String, params: Vec<Type>, returns: Option<Type>) -> Self {
+        FncState {
+            proto: Prototype::new(name, params, returns),
+            locals: vec![],
+            loops: vec![],
+            depth: 0,
+        }
+    }
+}
+
+enum Target {
+    Local(usize),
+    Capture(u8, usize),
+    Global(usize),
+}
+
+pub struct Compiler<'c> {
+    memory: &'c mut Memory,
+    states: Vec<FncState>,
+}
+
+impl<'c> Compiler<'c> {
+    pub fn new(memory: &'c mut Memory) -> Self {
+        Compiler {
+            memory,
+            states: vec![],
+        }
+    }
+
+    pub fn compile(mut self, ast: &Ast) -> Result<Closure, CompilerError> {
+        self.states
+            .push(FncState::new(String::from("<script>"), vec![], None));
+
+        // The value of a trailing expression statement becomes the result of the script
+        let (last, rest) = match ast.split_last() {
+            Some((
+                Stmt {
+                    kind: StmtKind::Expr(expr),
+                    line,
+                },
+                rest,
+            )) => (Some((expr, line)), rest),
+            _ => (None, &ast[..]),
+        };
+
+        for stmt in rest {
+            self.statement(stmt)?;
+        }
+
+        match last {
+            Some((expr, line)) => {
+                self.chunk().mark_line(*line);
+                self.expression(expr)?;
+            }
+            None => self.emit(OpCode::Empty),
+        }
+        self.emit(OpCode::Return);
+
+        let state = self.states.pop().unwrap();
+        Ok(Closure::new(state.proto))
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompilerError> {
+        self.chunk().mark_line(stmt.line);
+
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Pop);
+            }
+            StmtKind::Declare {
+                name,
+                init,
+                mutable,
+            } => {
+                // The initializer is compiled first so it still sees any shadowed binding
+                if let Some(expr) = init {
+                    self.expression(expr)?;
+                }
+
+                let (op, addr) = if self.is_global() {
+                    (OpCode::DefineGlobal, self.memory.declare(name, *mutable)?)
+                } else {
+                    (OpCode::SetLocal, self.declare_fresh(name, *mutable)?)
+                };
+                if init.is_some() {
+                    self.emit_address(op, addr)?;
+                }
+            }
+            StmtKind::Block(statements) => self.block(statements)?,
+            StmtKind::Condition {
+                test,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(test)?;
+                let then_jump = self.chunk().write_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.block(then_branch)?;
+
+                let else_jump = self.chunk().write_jump(OpCode::Jump);
+                self.chunk().patch_jump(then_jump)?;
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch)?;
+                }
+                self.chunk().patch_jump(else_jump)?;
+            }
+            StmtKind::Loop(body) => {
+                let start = self.begin_loop();
+
+                self.block(body)?;
+                self.chunk().write_loop(start)?;
+
+                let state = self.state().loops.pop().unwrap();
+                for jump in state.breaks {
+                    self.chunk().patch_jump(jump)?;
+                }
+            }
+            StmtKind::While { test, body } => {
+                let start = self.begin_loop();
+                self.expression(test)?;
+                let exit = self.chunk().write_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+
+                self.block(body)?;
+                self.chunk().write_loop(start)?;
+                self.end_loop(exit)?;
+            }
+            StmtKind::For {
+                index,
+                item,
+                iterable,
+                body,
+            } => {
+                // The iterable and the position reached live in hidden locals next to the loop
+                // variables, which share a scope with the body. The loop variables and the body's
+                // locals get new storage on each iteration
+                self.expression(iterable)?;
+                self.emit(OpCode::Iterable);
+                self.state().depth += 1;
+                let iterable = self.declare_local(" iterable", false)?;
+                self.emit_address(OpCode::SetLocal, iterable)?;
+                let position = self.declare_local(" position", true)?;
+                self.literal(Value::from(0.0))?;
+                self.emit_address(OpCode::SetLocal, position)?;
+
+                let start = self.begin_loop();
+                self.emit_address(OpCode::GetLocal, iterable)?;
+                self.emit_address(OpCode::GetLocal, position)?;
+                self.emit(OpCode::Iterate);
+                let exit = self.chunk().write_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+
+                let item = self.declare_fresh(item, false)?;
+                self.emit_address(OpCode::SetLocal, item)?;
+                if let Some(index) = index {
+                    let index = self.declare_fresh(index, false)?;
+                    self.emit_address(OpCode::GetLocal, position)?;
+                    self.emit_address(OpCode::SetLocal, index)?;
+                }
+                self.emit_address(OpCode::GetLocal, position)?;
+                self.literal(Value::from(1.0))?;
+                self.emit(OpCode::Add);
+                self.emit_address(OpCode::SetLocal, position)?;
+
+                for stmt in body {
+                    self.statement(stmt)?;
+                }
+                let locals = self.state().loops.last().unwrap().locals;
+                self.clear_locals(locals)?;
+                self.chunk().write_loop(start)?;
+                self.end_loop(exit)?;
+                self.end_scope()?;
+            }
+            StmtKind::Break => {
+                if self.state().loops.is_empty() {
+                    Err(CompilerError::IllegalDivergence(
+                        Divergence::Break.to_string(),
+                    ))?
+                }
+                let locals = self.state().loops.last().unwrap().locals;
+                self.clear_locals(locals)?;
+                let jump = self.chunk().write_jump(OpCode::Jump);
+                self.state().loops.last_mut().unwrap().breaks.push(jump);
+            }
+            StmtKind::Continue => {
+                let Some(&LoopState { start, locals, .. }) = self.state().loops.last() else {
+                    Err(CompilerError::IllegalDivergence(
+                        Divergence::Continue.to_string(),
+                    ))?
+                };
+                self.clear_locals(locals)?;
+                self.chunk().write_loop(start)?;
+            }
+            StmtKind::Return(expr) => {
+                if self.states.len() == 1 {
+                    Err(CompilerError::IllegalDivergence(
+                        Divergence::Return(Value::Empty).to_string(),
+                    ))?
+                }
+                self.expression(expr)?;
+                self.emit(OpCode::Return);
+            }
+            StmtKind::Exit => Err(CompilerError::IllegalDivergence(
+                Divergence::Exit.to_string(),
+            ))?,
+            StmtKind::Fnc {
+                name,
+                params,
+                returns,
+                body,
+            } => {
+                // Bind the name before compiling the body so the function can call itself
+                let target = if self.is_global() {
+                    Target::Global(self.memory.declare(name, false)?)
+                } else {
+                    Target::Local(self.declare_fresh(name, false)?)
+                };
+
+                self.function(name, params, returns, body)?;
+
+                match target {
+                    Target::Global(addr) => self.emit_address(OpCode::DefineGlobal, addr)?,
+                    Target::Local(slot) => self.emit_address(OpCode::SetLocal, slot)?,
+                    Target::Capture(..) => unreachable!(),
+                }
+            }
+            StmtKind::Type {
+                name,
+                fields,
+                methods,
+            } => {
+                let ty = RecordType {
+                    name: name.to_string(),
+                    fields: fields.to_owned(),
+                    methods: vec![],
+                };
+                self.literal(Value::from(ty))?;
+                // Methods close over the declaring scope, so they are attached at runtime
+                if !methods.is_empty() {
+                    for method in methods {
+                        self.literal(Value::from(&method.name))?;
+                        let qualified = format!("{}.{}", name, method.name);
+                        self.function(&qualified, &method.params, &method.returns, &method.body)?;
+                    }
+                    self.emit_address(OpCode::Methods, methods.len())?;
+                }
+                self.define(name)?;
+            }
+            StmtKind::Enum { name, variants } => {
+                let ty = EnumType {
+                    name: name.to_string(),
+                    variants: variants.to_owned(),
+                };
+                self.literal(Value::from(ty))?;
+                self.define(name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Binds `name` immutably to the value on top of the stack
+    fn define(&mut self, name: &str) -> Result<(), CompilerError> {
+        if self.is_global() {
+            let addr = self.memory.declare(name, false)?;
+            self.emit_address(OpCode::DefineGlobal, addr)
+        } else {
+            let slot = self.declare_fresh(name, false)?;
+            self.emit_address(OpCode::SetLocal, slot)
+        }
+    }
+
+    fn block(&mut self, statements: &Ast) -> Result<(), CompilerError> {
+        self.state().depth += 1;
+        for stmt in statements {
+            self.statement(stmt)?;
+        }
+        self.end_scope()?;
+
+        Ok(())
+    }
+
+    fn end_scope(&mut self) -> Result<(), CompilerError> {
+        let state = self.state();
+        state.depth -= 1;
+        let depth = state.depth;
+        let end = state.locals.iter().position(|local| local.depth > depth);
+        if let Some(end) = end {
+            self.clear_locals(end)?;
+            self.state().locals.truncate(end);
+        }
+
+        Ok(())
+    }
+
+    /// Empties the slots of every local after the first `keep` before they go out of scope, so
+    /// that closures created on a later pass through the same code can't capture them
+    fn clear_locals(&mut self, keep: usize) -> Result<(), CompilerError> {
+        let slots: Vec<usize> = self.state().locals[keep..]
+            .iter()
+            .map(|local| local.slot)
+            .collect();
+        for slot in slots {
+            self.emit_address(OpCode::ClearLocal, slot)?;
+        }
+
+        Ok(())
+    }
+
+    fn begin_loop(&mut self) -> usize {
+        let start = self.chunk().len();
+        let locals = self.state().locals.len();
+        self.state().loops.push(LoopState {
+            start,
+            breaks: vec![],
+            locals,
+        });
+        start
+    }
+
+    /// Lands the loop's exit jump, which leaves its condition on the stack, then its breaks
+    fn end_loop(&mut self, exit: usize) -> Result<(), CompilerError> {
+        self.chunk().patch_jump(exit)?;
+        self.emit(OpCode::Pop);
+
+        let state = self.state().loops.pop().unwrap();
+        for jump in state.breaks {
+            self.chunk().patch_jump(jump)?;
+        }
+
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        name: &str,
+        params: &[Param],
+        returns: &Type,
+        body: &Ast,
+    ) -> Result<(), CompilerError> {
+        let types = params.iter().map(|p| p.ty.clone()).collect();
+        self.states.push(FncState::new(
+            name.to_string(),
+            types,
+            Some(returns.clone()),
+        ));
+        self.state().proto.required = params.iter().filter(|p| p.default.is_none()).count();
+
+        for (slot, param) in params.iter().enumerate() {
+            // Fill in omitted arguments before the parameter itself comes into scope
+            if let Some(default) = &param.default {
+                self.emit_address(OpCode::IsSet, slot)?;
+                let provided = self.chunk().write_jump(OpCode::JumpIfTrue);
+                self.emit(OpCode::Pop);
+                self.expression(default)?;
+                self.emit_address(OpCode::SetLocal, slot)?;
+                self.emit_address(OpCode::CheckParam, slot)?;
+                let end = self.chunk().write_jump(OpCode::Jump);
+                self.chunk().patch_jump(provided)?;
+                self.emit(OpCode::Pop);
+                self.chunk().patch_jump(end)?;
+            }
+            self.declare_local(&param.name, true)?;
+        }
+
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        self.emit(OpCode::Empty);
+        self.emit(OpCode::Return);
+
+        let state = self.states.pop().unwrap();
+        let addr = self
+            .memory
+            .declare_constant(Value::from(Closure::new(state.proto)));
+        self.emit_address(OpCode::Closure, addr)
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), CompilerError> {
+        match expr {
+            Expr::Literal(value) => self.literal(value.clone())?,
+            Expr::Interpolation(segments) => {
+                for (i, segment) in segments.iter().enumerate() {
+                    match segment {
+                        Segment::Text(text) => self.literal(Value::String(boxed!(text.clone())))?,
+                        Segment::Value(expr, spec) => {
+                            self.expression(expr)?;
+                            let spec = Value::String(boxed!(spec.to_string()));
+                            let addr = self.memory.declare_constant(spec);
+                            self.emit_address(OpCode::Format, addr)?;
+                        }
+                    }
+                    if i > 0 {
+                        self.emit(OpCode::Add);
+                    }
+                }
+            }
+            Expr::List(expressions) => {
+                for expr in expressions {
+                    self.expression(expr)?;
+                }
+                self.emit_address(OpCode::List, expressions.len())?;
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.emit_address(OpCode::Map, entries.len())?;
+            }
+            Expr::Grouping { expr } => self.expression(expr)?,
+            Expr::UnOp { expr, op } => {
+                self.expression(expr)?;
+                match op {
+                    Unary::Not => self.emit(OpCode::Not),
+                    Unary::Negate => self.emit(OpCode::Negate),
+                }
+            }
+            Expr::BinOp { left, right, op } => {
+                self.expression(left)?;
+
+                if op.logical() {
+                    let jump = match op {
+                        Binary::And => OpCode::JumpIfFalse,
+                        _ => OpCode::JumpIfTrue,
+                    };
+                    let end = self.chunk().write_jump(jump);
+                    self.emit(OpCode::Pop);
+                    self.expression(right)?;
+                    self.chunk().patch_jump(end)?;
+                    return Ok(());
+                }
+
+                self.expression(right)?;
+                self.emit(binary(op));
+            }
+            Expr::Binding { name, .. } => self.load(name)?,
+            Expr::Assign { name, expr, .. } => {
+                self.expression(expr)?;
+                self.store(name)?;
+                self.emit(OpCode::Empty);
+            }
+            Expr::Call { callee, args } => {
+                self.expression(callee)?;
+                for arg in args {
+                    self.expression(arg)?;
+                }
+
+                if args.len() > u8::MAX as usize {
+                    Err(CompilerError::TooManyArguments(args.len()))?
+                }
+                self.emit_address(OpCode::Call, args.len())?;
+            }
+            Expr::Lambda {
+                params,
+                returns,
+                body,
+            } => self.function("lambda", params, returns, body)?,
+            Expr::Match { subject, arms } => {
+                // The subject is kept in a hidden local while each arm's pattern is tried on it
+                self.expression(subject)?;
+                self.state().depth += 1;
+                let subject = self.declare_local(" subject", false)?;
+                self.emit_address(OpCode::SetLocal, subject)?;
+
+                let mut ends = vec![];
+                for arm in arms {
+                    let pattern = self.state().proto.patterns.len();
+                    self.state().proto.patterns.push(arm.pattern.clone());
+                    self.emit_address(OpCode::GetLocal, subject)?;
+                    self.emit_address(OpCode::Match, pattern)?;
+                    let next = self.chunk().write_jump(OpCode::JumpIfFalse);
+                    self.emit(OpCode::Pop);
+
+                    // Bound values are pushed left to right, so they are stored in reverse
+                    self.state().depth += 1;
+                    let mut slots = vec![];
+                    for name in arm.pattern.bindings() {
+                        slots.push(self.declare_fresh(name, false)?);
+                    }
+                    for slot in slots.into_iter().rev() {
+                        self.emit_address(OpCode::SetLocal, slot)?;
+                    }
+                    self.expression(&arm.body)?;
+                    self.end_scope()?;
+
+                    ends.push(self.chunk().write_jump(OpCode::Jump));
+                    self.chunk().patch_jump(next)?;
+                    self.emit(OpCode::Pop);
+                }
+                self.emit_address(OpCode::GetLocal, subject)?;
+                self.emit(OpCode::NoMatch);
+
+                for end in ends {
+                    self.chunk().patch_jump(end)?;
+                }
+                self.end_scope()?;
+            }
+            Expr::Get { object, field } => {
+                self.expression(object)?;
+                let addr = self.memory.declare_constant(Value::from(field));
+                self.emit_address(OpCode::GetField, addr)?;
+            }
+            Expr::Set {
+                object,
+                field,
+                op,
+                expr,
+            } => {
+                let addr = self.memory.declare_constant(Value::from(field));
+                self.expression(object)?;
+                if let Some(op) = op {
+                    self.emit_address(OpCode::Duplicate, 1)?;
+                    self.emit_address(OpCode::GetField, addr)?;
+                    self.expression(expr)?;
+                    self.emit(binary(op));
+                } else {
+                    self.expression(expr)?;
+                }
+                self.emit_address(OpCode::SetField, addr)?;
+                self.emit(OpCode::Empty);
+            }
+            Expr::Index { object, index } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.emit(OpCode::GetIndex);
+            }
+            Expr::Slice {
+                object,
+                start,
+                end,
+                step,
+            } => {
+                self.expression(object)?;
+                for part in [start, end, step] {
+                    match part {
+                        Some(expr) => self.expression(expr)?,
+                        None => self.emit(OpCode::Empty),
+                    }
+                }
+                self.emit(OpCode::Slice);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                op,
+                expr,
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                if let Some(op) = op {
+                    self.emit_address(OpCode::Duplicate, 2)?;
+                    self.emit(OpCode::GetIndex);
+                    self.expression(expr)?;
+                    self.emit(binary(op));
+                } else {
+                    self.expression(expr)?;
+                }
+                self.emit(OpCode::SetIndex);
+                self.emit(OpCode::Empty);
+            }
+            Expr::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                self.expression(start)?;
+                self.expression(end)?;
+                match step {
+                    Some(step) => self.expression(step)?,
+                    None => self.emit(OpCode::Empty),
+                }
+                self.emit(match inclusive {
+                    true => OpCode::RangeInclusive,
+                    false => OpCode::Range,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn literal(&mut self, value: Value) -> Result<(), CompilerError> {
+        match value {
+            Value::Empty => self.emit(OpCode::Empty),
+            Value::Boolean(true) => self.emit(OpCode::True),
+            Value::Boolean(false) => self.emit(OpCode::False),
+            value => {
+                let addr = self.memory.declare_constant(value);
+                self.emit_address(OpCode::Constant, addr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load(&mut self, name: &str) -> Result<(), CompilerError> {
+        match self.resolve(name).0 {
+            Target::Local(slot) => self.emit_address(OpCode::GetLocal, slot),
+            Target::Capture(depth, slot) => self.emit_capture(OpCode::GetCapture, depth, slot),
+            Target::Global(addr) => self.emit_address(OpCode::GetGlobal, addr),
+        }
+    }
+
+    fn store(&mut self, name: &str) -> Result<(), CompilerError> {
+        let (target, mutable) = self.resolve(name);
+        if !mutable {
+            Err(MemoryError::AssignToImmutable(name.to_string()))?
+        }
+
+        match target {
+            Target::Local(slot) => self.emit_address(OpCode::SetLocal, slot),
+            Target::Capture(depth, slot) => self.emit_capture(OpCode::SetCapture, depth, slot),
+            Target::Global(addr) => self.emit_address(OpCode::SetGlobal, addr),
+        }
+    }
+
+    /// Looks a name up through enclosing functions, falling back to a late-bound global.
+    /// Globals are reported as mutable here and checked when they are assigned at runtime
+    fn resolve(&mut self, name: &str) -> (Target, bool) {
+        for (depth, state) in self.states.iter().rev().enumerate() {
+            let Some(local) = state.locals.iter().rev().find(|l| l.name == name) else {
+                continue;
+            };
+
+            let target = match depth {
+                0 => Target::Local(local.slot),
+                depth => Target::Capture(depth as u8, local.slot),
+            };
+            return (target, local.mutable);
+        }
+
+        (Target::Global(self.memory.reserve(name)), true)
+    }
+
+    fn declare_local(&mut self, name: &str, mutable: bool) -> Result<usize, CompilerError> {
+        let state = self.state();
+        let shadows_var = state
+            .locals
+            .iter()
+            .any(|l| l.name == name && l.depth == state.depth && l.mutable);
+        if shadows_var {
+            Err(MemoryError::AlreadyDeclared(name.to_string()))?
+        }
+
+        // Slots are never reused, since a closure may still hold the frame they live in
+        let slot = state.proto.locals.len();
+        state.proto.locals.push(name.to_string());
+        state.locals.push(Local {
+            name: name.to_string(),
+            depth: state.depth,
+            slot,
+            mutable,
+        });
+        Ok(slot)
+    }
+
+    /// Declares a local that gets new storage each time its declaration runs, as each pass
+    /// through a block does in the tree-walker
+    fn declare_fresh(&mut self, name: &str, mutable: bool) -> Result<usize, CompilerError> {
+        let slot = self.declare_local(name, mutable)?;
+        self.emit_address(OpCode::ClearLocal, slot)?;
+        Ok(slot)
+    }
+
+    fn is_global(&self) -> bool {
+        self.states.len() == 1 && self.states[0].depth == 0
+    }
+
+    fn state(&mut self) -> &mut FncState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().proto.chunk
+    }
+
+    fn emit(&mut self, op: OpCode) {
+        self.chunk().write(op);
+    }
+
+    fn emit_address(&mut self, op: OpCode, addr: usize) -> Result<(), CompilerError> {
+        self.chunk().write_address(op, addr)
+    }
+
+    fn emit_capture(&mut self, op: OpCode, depth: u8, slot: usize) -> Result<(), CompilerError> {
+        self.chunk().write_capture(op, depth, slot)
+    }
+}
+
+/// The instruction for a binary operator. Logical operators are compiled as jumps instead
+fn binary(op: &Binary) -> OpCode {
+    match op {
+        Binary::Add => OpCode::Add,
+        Binary::Subtract => OpCode::Subtract,
+        Binary::Multiply => OpCode::Multiply,
+        Binary::Divide => OpCode::Divide,
+        Binary::Modulo => OpCode::Modulo,
+        Binary::Equality => OpCode::Equal,
+        Binary::Inequality => OpCode::NotEqual,
+        Binary::Identity => OpCode::Is,
+        Binary::Membership => OpCode::In,
+        Binary::GreaterThan => OpCode::Greater,
+        Binary::GreaterOrEqual => OpCode::GreaterEqual,
+        Binary::LessThan => OpCode::Less,
+        Binary::LessOrEqual => OpCode::LessEqual,
+        Binary::And | Binary::Or => unreachable!(),
+    }
+}
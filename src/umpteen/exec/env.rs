@@ -1,101 +1,130 @@
-use std::collections::HashMap;
-
-use uuid::Uuid;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
 
 use crate::{
     error::MemoryError,
+    exec::heap,
     repr::{
+        ast::expr::{Local, Resolved},
         fnc::{Call, NativeFnc},
-        object::Object,
         value::Value,
     },
 };
 
-#[derive(Debug, Default)]
-pub struct Memory {
-    vars: HashMap<String, Option<Value>>,
-    env_id: Uuid,
-    parent: Option<Uuid>,
+#[derive(Debug)]
+pub(crate) struct Binding {
+    value: Option<Value>,
+    mutable: bool,
 }
 
-impl Memory {
-    pub fn new(parent: Option<Uuid>) -> Self {
-        Memory {
-            vars: HashMap::new(),
-            env_id: Uuid::new_v4(),
-            parent,
-        }
+impl Binding {
+    pub(crate) fn value(&self) -> Option<&Value> {
+        self.value.as_ref()
     }
 
-    pub fn declare(&mut self, name: &str) -> Result<(), MemoryError> {
-        if self.vars.contains_key(name) {
-            panic!("variable `{name}` already declared")
-        } else {
-            self.vars.insert(name.to_string(), None);
+    fn assign(&mut self, name: &str, value: Value) -> Result<(), MemoryError> {
+        if !self.mutable && self.value.is_some() {
+            Err(MemoryError::AssignToImmutable(name.to_string()))?
         }
 
+        self.value = Some(value);
+
         Ok(())
     }
 
-    pub fn assign(
-        &mut self,
+    fn get(&self, name: &str) -> Result<Value, MemoryError> {
+        let Some(var) = &self.value else {
+            Err(MemoryError::UninitializedVariable(name.to_owned()))?
+        };
+
+        Ok(var.clone())
+    }
+}
+
+thread_local! {
+    static LIVE_SCOPES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The bindings of one block, in the order they were declared. That is the order the resolver
+/// numbers slots in, so a resolved local is read by indexing rather than by name. A scope lives
+/// for as long as a block running in it, or a function defined in it, still holds a reference
+#[derive(Debug)]
+pub struct Scope {
+    slots: RefCell<Vec<(String, Binding)>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    pub fn new(parent: Option<Rc<Scope>>) -> Rc<Self> {
+        LIVE_SCOPES.set(LIVE_SCOPES.get() + 1);
+        let scope = Rc::new(Scope {
+            slots: RefCell::new(vec![]),
+            parent,
+        });
+        heap::track_scope(&scope);
+        scope
+    }
+
+    /// Declares `name` in the next slot. Only immutable bindings may be shadowed in the same scope
+    pub fn declare(
+        &self,
         name: &str,
-        index: Option<usize>,
-        value: Value,
+        mutable: bool,
+        value: Option<Value>,
     ) -> Result<(), MemoryError> {
-        if self.vars.contains_key(name) {
-            if let Some(idx) = index {
-                if let Some(Some(Value::Object(obj))) = self.vars.get_mut(name) {
-                    if let Object::List(ref mut list) = *obj.borrow_mut() {
-                        if idx >= list.len() {
-                            list.resize(idx + 1, Value::Empty);
-                        }
-                        list[idx] = value;
-                        return Ok(());
-                    }
-                }
-            } else {
-                self.vars.insert(name.to_string(), Some(value));
-            }
-        } else {
-            Err(MemoryError::NoSuchVariable(name.to_string()))?
+        let mut slots = self.slots.borrow_mut();
+        if let Some((_, Binding { mutable: true, .. })) = slots.iter().rfind(|(n, _)| n == name) {
+            Err(MemoryError::AlreadyDeclared(name.to_string()))?
         }
 
+        slots.push((name.to_string(), Binding { value, mutable }));
+
         Ok(())
     }
 
-    pub fn get(&self, name: &str, index: Option<usize>) -> Result<Value, MemoryError> {
-        let Some(Some(var)) = self.vars.get(name) else {
-            Err(MemoryError::UninitializedVariable(name.to_owned()))?
-        };
-
-        if let Some(idx) = index {
-            if let Value::String(s) = var {
-                return Ok(Value::from(&s[idx..idx + 1]));
-            }
+    pub fn assign(&self, slot: usize, name: &str, value: Value) -> Result<(), MemoryError> {
+        match self.slots.borrow_mut().get_mut(slot) {
+            Some((_, binding)) => binding.assign(name, value),
+            None => Err(MemoryError::NoSuchVariable(name.to_string())),
+        }
+    }
 
-            if let Value::Object(obj) = var {
-                if let Object::List(ref list) = *obj.borrow() {
-                    return Ok(list[idx].clone());
-                }
-            } else {
-                Err(MemoryError::CannotIndex(name.to_string()))?;
-            }
+    pub fn get(&self, slot: usize, name: &str) -> Result<Value, MemoryError> {
+        match self.slots.borrow().get(slot) {
+            Some((_, binding)) => binding.get(name),
+            None => Err(MemoryError::NoSuchVariable(name.to_owned())),
         }
+    }
 
-        Ok(var.clone())
+    pub fn parent(&self) -> Option<&Rc<Scope>> {
+        self.parent.as_ref()
     }
 
-    pub fn parent(&self) -> Option<Uuid> {
-        self.parent
+    pub(crate) fn slots(&self) -> &RefCell<Vec<(String, Binding)>> {
+        &self.slots
     }
 }
 
+impl Drop for Scope {
+    fn drop(&mut self) {
+        LIVE_SCOPES.set(LIVE_SCOPES.get() - 1);
+    }
+}
+
+/// The number of scopes currently alive on this thread
+pub fn live_scopes() -> usize {
+    LIVE_SCOPES.get()
+}
+
+/// The globals, looked up by name and then by which declaration of that name, plus the chain of
+/// scopes enclosing the code currently running
 #[derive(Debug)]
 pub struct Env {
-    scopes: HashMap<Uuid, Memory>,
-    glob_key: Uuid,
-    current: Option<Uuid>,
+    globals: HashMap<String, Vec<Binding>>,
+    current: Option<Rc<Scope>>,
 }
 
 impl Env {
@@ -103,117 +132,121 @@ impl Env {
         Self::default()
     }
 
-    pub fn get(&self, name: &str, index: Option<usize>) -> Result<Value, MemoryError> {
-        let mut maybe_mem = Some(self.mem());
+    /// Reads a binding resolved ahead of time, either at a slot `depth` scopes out or in the
+    /// globals
+    pub fn get_at(&self, resolved: Resolved, name: &str) -> Result<Value, MemoryError> {
+        match resolved {
+            Resolved::Local(Local { depth, slot }) => self.ancestor(depth)?.get(slot, name),
+            Resolved::Global(nth) => match self.globals.get(name).and_then(|b| b.get(nth)) {
+                Some(binding) => binding.get(name),
+                None => Err(MemoryError::NoSuchVariable(name.to_owned())),
+            },
+        }
+    }
 
-        while let Some(mem) = maybe_mem {
-            if let Ok(val) = mem.get(name, index) {
-                return Ok(val);
-            } else if let Some(id) = mem.parent {
-                maybe_mem = self.retrieve(id);
-            } else {
-                let mem = self.retrieve(self.glob_key).unwrap();
-                return mem.get(name, index);
-            }
+    /// Declares `name` in the current scope, or as a global outside of any block. Shadowing a
+    /// global leaves the old binding to whatever was resolved to it
+    pub fn declare(
+        &mut self,
+        name: &str,
+        mutable: bool,
+        value: Option<Value>,
+    ) -> Result<(), MemoryError> {
+        if let Some(scope) = &self.current {
+            return scope.declare(name, mutable, value);
+        }
+
+        let bindings = self.globals.entry(name.to_string()).or_default();
+        if let Some(Binding { mutable: true, .. }) = bindings.last() {
+            Err(MemoryError::AlreadyDeclared(name.to_string()))?
         }
+        bindings.push(Binding { value, mutable });
 
-        Err(MemoryError::NoSuchVariable(name.to_owned()))
+        Ok(())
     }
 
-    pub fn declare(&mut self, name: &str) -> Result<(), MemoryError> {
-        self.mem_mut().declare(name)
+    /// How many times `name` has been declared as a global
+    pub fn declarations(&self, name: &str) -> usize {
+        self.globals.get(name).map_or(0, Vec::len)
     }
 
-    pub fn assign(
+    pub fn assign_at(
         &mut self,
+        resolved: Resolved,
         name: &str,
-        index: Option<usize>,
         value: Value,
     ) -> Result<(), MemoryError> {
-        let mut maybe_mem = Some(self.mem_mut());
-        while let Some(mem) = maybe_mem.as_deref_mut() {
-            if mem.vars.contains_key(name) {
-                return mem.assign(name, index, value);
-            } else if let Some(id) = mem.parent {
-                maybe_mem = self.retrieve_mut(id);
-            } else {
-                let mem = self.retrieve_mut(self.glob_key).unwrap();
-                return mem.assign(name, index, value);
+        match resolved {
+            Resolved::Local(Local { depth, slot }) => {
+                self.ancestor(depth)?.assign(slot, name, value)
+            }
+            Resolved::Global(nth) => {
+                match self.globals.get_mut(name).and_then(|b| b.get_mut(nth)) {
+                    Some(binding) => binding.assign(name, value),
+                    None => Err(MemoryError::NoSuchVariable(name.to_string())),
+                }
             }
         }
-
-        Err(MemoryError::NoSuchVariable(name.to_owned()))
-    }
-
-    pub fn set_current(&mut self, id: Option<Uuid>) -> Option<Uuid> {
-        let current = self.current;
-        self.current = id;
-        current
-    }
-
-    pub fn new_enclosed(&mut self) -> Uuid {
-        let key = Uuid::new_v4();
-        let mem = Memory::new(self.current);
-
-        self.scopes.insert(key, mem);
-        key
     }
 
-    fn retrieve(&self, id: Uuid) -> Option<&Memory> {
-        self.scopes.get(&id)
+    fn ancestor(&self, depth: usize) -> Result<&Scope, MemoryError> {
+        let mut scope = self.current.as_ref();
+        for _ in 0..depth {
+            scope = scope.and_then(|scope| scope.parent());
+        }
+        scope
+            .map(|scope| &**scope)
+            .ok_or(MemoryError::OutOfBoundsMemoryAccess)
     }
 
-    pub fn retrieve_mut(&mut self, id: Uuid) -> Option<&mut Memory> {
-        self.scopes.get_mut(&id)
+    pub fn set_current(&mut self, scope: Option<Rc<Scope>>) -> Option<Rc<Scope>> {
+        std::mem::replace(&mut self.current, scope)
     }
 
-    fn mem(&self) -> &Memory {
-        match self.current {
-            Some(id) => self.scopes.get(&id).unwrap(),
-            None => self.scopes.get(&self.glob_key).unwrap(),
-        }
+    pub fn current(&self) -> Option<Rc<Scope>> {
+        self.current.clone()
     }
 
-    fn mem_mut(&mut self) -> &mut Memory {
-        match self.current {
-            Some(id) => self.scopes.get_mut(&id).unwrap(),
-            None => self.scopes.get_mut(&self.glob_key).unwrap(),
-        }
+    pub fn new_enclosed(&self) -> Rc<Scope> {
+        Scope::new(self.current())
     }
 }
 
-macro_rules! builtin {
-    ($f:tt) => {
-        (
-            String::from(NativeFnc::$f.name()),
-            Some(Value::from(NativeFnc::$f)),
-        )
-    };
-}
-
 impl Default for Env {
     fn default() -> Self {
-        let builtins = HashMap::from([
-            builtin!(Print),
-            builtin!(Printx),
-            builtin!(Time),
-            builtin!(Str),
-            builtin!(Len),
-            builtin!(Chr),
-            builtin!(Ord),
-        ]);
-        let glob_key = Uuid::new_v4();
-        let globals = Memory {
-            vars: builtins,
-            env_id: glob_key,
-            parent: None,
-        };
-        let scopes = HashMap::from([(glob_key, globals)]);
+        let globals = NativeFnc::ALL
+            .into_iter()
+            .map(|f| {
+                let binding = Binding {
+                    value: Some(Value::from(f.clone())),
+                    mutable: false,
+                };
+                (f.name(), vec![binding])
+            })
+            .collect();
 
         Env {
-            scopes,
-            glob_key,
+            globals,
             current: None,
         }
     }
 }
+
+#[test]
+fn scopes_are_freed_once_nothing_holds_them() {
+    let mut env = Env::new();
+    let outer = env.new_enclosed();
+    env.set_current(Some(outer.clone()));
+    let inner = env.new_enclosed();
+    let weak = Rc::downgrade(&inner);
+    let held = inner.clone(); // As a function defined in `inner` would
+
+    drop(inner);
+    env.set_current(None);
+    drop(outer);
+    assert!(weak.upgrade().is_some());
+    assert!(held.parent().is_some(), "a held scope keeps its parents");
+
+    drop(held);
+    assert!(weak.upgrade().is_none());
+}
@@ -0,0 +1,411 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem::{size_of, size_of_val},
+    rc::{Rc, Weak},
+};
+
+use crate::repr::{fnc::Fnc, map::Key, object::Object, value::Value};
+
+use super::{
+    env::{Binding, Scope},
+    vm::{Frame, Slot},
+};
+
+/// Knobs controlling how often the collector runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    pub initial_threshold: usize, // Allocations before the first collection
+    pub growth_factor: f64,       // Next threshold as a multiple of what survived a collection
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            initial_threshold: 1024,
+            growth_factor: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub allocations: usize,
+    pub collections: usize,
+    pub live_bytes: usize,
+}
+
+enum Node {
+    Object(Weak<RefCell<Object>>),
+    Frame(Weak<Frame>),
+    Scope(Weak<Scope>),
+    Cell(Weak<RefCell<Option<Value>>>),
+}
+
+enum Handle {
+    Object(Rc<RefCell<Object>>),
+    Frame(Rc<Frame>),
+    Scope(Rc<Scope>),
+    Cell(Rc<RefCell<Option<Value>>>),
+}
+
+impl Handle {
+    fn key(&self) -> usize {
+        match self {
+            Handle::Object(obj) => Rc::as_ptr(obj) as *const () as usize,
+            Handle::Frame(frame) => Rc::as_ptr(frame) as *const () as usize,
+            Handle::Scope(scope) => Rc::as_ptr(scope) as *const () as usize,
+            Handle::Cell(cell) => Rc::as_ptr(cell) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Handle::Object(obj) => Rc::strong_count(obj),
+            Handle::Frame(frame) => Rc::strong_count(frame),
+            Handle::Scope(scope) => Rc::strong_count(scope),
+            Handle::Cell(cell) => Rc::strong_count(cell),
+        }
+    }
+
+    /// Addresses of the heap cells this one holds a strong reference to, or None if it is
+    /// currently borrowed and can't be inspected
+    fn children(&self) -> Option<Vec<usize>> {
+        let mut children = vec![];
+        let visit = |value: &Value, children: &mut Vec<usize>| {
+            if let Value::Object(obj) = value {
+                children.push(Rc::as_ptr(obj) as *const () as usize);
+            }
+        };
+        let env = |fnc: &Fnc| match fnc {
+            Fnc::Compiled(closure) => closure
+                .env()
+                .map(|env| Rc::as_ptr(env) as *const () as usize),
+            Fnc::User(fnc) => fnc
+                .closure()
+                .map(|scope| Rc::as_ptr(scope) as *const () as usize),
+            _ => None,
+        };
+
+        match self {
+            Handle::Object(obj) => match &*obj.try_borrow().ok()? {
+                Object::List(values) => values.iter().for_each(|v| visit(v, &mut children)),
+                Object::Map(map) => map.values().for_each(|v| visit(v, &mut children)),
+                Object::Record(record) => {
+                    record.values().iter().for_each(|v| visit(v, &mut children))
+                }
+                Object::Variant(instance) => instance
+                    .values()
+                    .iter()
+                    .for_each(|v| visit(v, &mut children)),
+                Object::Fnc(Fnc::Bound(receiver, method)) => {
+                    visit(receiver, &mut children);
+                    children.extend(env(method));
+                }
+                // A record type's methods belong to the one constructor object holding it
+                Object::Fnc(Fnc::Record(ty)) => {
+                    children.extend(ty.methods.iter().filter_map(|(_, method)| env(method)))
+                }
+                Object::Fnc(fnc) => children.extend(env(fnc)),
+                Object::Range(_) | Object::Enum(_) => (),
+            },
+            Handle::Frame(frame) => {
+                for slot in frame.slots().try_borrow().ok()?.iter() {
+                    match slot {
+                        Slot::Plain(Some(value)) => visit(value, &mut children),
+                        Slot::Plain(None) => (),
+                        Slot::Shared(cell) => children.push(Rc::as_ptr(cell) as *const () as usize),
+                    }
+                }
+                if let Some(parent) = frame.parent() {
+                    children.push(Rc::as_ptr(parent) as *const () as usize);
+                }
+            }
+            Handle::Scope(scope) => {
+                scope
+                    .slots()
+                    .try_borrow()
+                    .ok()?
+                    .iter()
+                    .filter_map(|(_, binding)| binding.value())
+                    .for_each(|v| visit(v, &mut children));
+                if let Some(parent) = scope.parent() {
+                    children.push(Rc::as_ptr(parent) as *const () as usize);
+                }
+            }
+            Handle::Cell(cell) => {
+                if let Some(value) = &*cell.try_borrow().ok()? {
+                    visit(value, &mut children);
+                }
+            }
+        }
+
+        Some(children)
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Handle::Object(obj) => {
+                let contents = match &*obj.borrow() {
+                    Object::List(values) => values.capacity() * size_of::<Value>(),
+                    Object::Map(map) => map.capacity() * size_of::<(Key, Value)>(),
+                    Object::Record(record) => size_of_val(record.values()),
+                    Object::Variant(instance) => size_of_val(instance.values()),
+                    Object::Fnc(_) | Object::Range(_) | Object::Enum(_) => 0,
+                };
+                size_of::<RefCell<Object>>() + contents
+            }
+            Handle::Frame(frame) => {
+                size_of::<Frame>() + frame.slots().borrow().len() * size_of::<Slot>()
+            }
+            Handle::Scope(scope) => {
+                size_of::<Scope>()
+                    + scope.slots().borrow().capacity() * size_of::<(String, Binding)>()
+            }
+            Handle::Cell(_) => size_of::<RefCell<Option<Value>>>(),
+        }
+    }
+
+    /// Drops everything this cell refers to, breaking any cycle running through it
+    fn clear(&self) {
+        match self {
+            Handle::Object(obj) => {
+                if let Ok(mut obj) = obj.try_borrow_mut() {
+                    match &mut *obj {
+                        Object::List(values) => values.clear(),
+                        Object::Map(map) => map.clear(),
+                        Object::Record(record) => record.clear(),
+                        Object::Variant(instance) => instance.clear(),
+                        Object::Fnc(Fnc::Compiled(closure)) => closure.release(),
+                        Object::Fnc(Fnc::User(fnc)) => fnc.release(),
+                        Object::Fnc(Fnc::Bound(receiver, method)) => {
+                            *receiver = Value::Empty;
+                            match &mut **method {
+                                Fnc::Compiled(closure) => closure.release(),
+                                Fnc::User(fnc) => fnc.release(),
+                                _ => (),
+                            }
+                        }
+                        Object::Fnc(_) | Object::Range(_) | Object::Enum(_) => (),
+                    }
+                }
+            }
+            Handle::Frame(frame) => {
+                if let Ok(mut slots) = frame.slots().try_borrow_mut() {
+                    slots.clear();
+                }
+            }
+            Handle::Scope(scope) => {
+                if let Ok(mut slots) = scope.slots().try_borrow_mut() {
+                    slots.clear();
+                }
+            }
+            Handle::Cell(cell) => {
+                if let Ok(mut value) = cell.try_borrow_mut() {
+                    value.take();
+                }
+            }
+        }
+    }
+}
+
+/// Tracks every object, scope, call frame and captured local so that reference cycles, which
+/// reference counting alone would leak, can be reclaimed
+///
+/// Objects are freed as soon as their last reference goes away. The collector only has to
+/// find cells that are kept alive by each other. Scopes of the tree-walker are cells too, so a
+/// function stored in the scope it closes over is no different from any other cycle. The roots
+/// are the cells referenced from outside the heap (the globals and the scopes of running blocks,
+/// the VM's stack and call frames), found by subtracting the references cells hold to each other
+/// from their reference counts. Everything reachable
+/// from a root is marked, and anything left is swept by clearing its contents.
+pub struct Heap {
+    nodes: Vec<Node>,
+    config: GcConfig,
+    threshold: usize,
+    since_collection: usize,
+    allocations: usize,
+    collections: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::new(GcConfig::default()));
+}
+
+impl Heap {
+    fn new(config: GcConfig) -> Self {
+        Heap {
+            nodes: vec![],
+            threshold: config.initial_threshold,
+            config,
+            since_collection: 0,
+            allocations: 0,
+            collections: 0,
+        }
+    }
+
+    fn track(&mut self, node: Node) {
+        self.nodes.push(node);
+        self.allocations += 1;
+        self.since_collection += 1;
+        if self.since_collection >= self.threshold {
+            self.collect();
+        }
+    }
+
+    fn live(&mut self) -> Vec<Handle> {
+        let mut live = vec![];
+        self.nodes.retain(|node| {
+            let handle = match node {
+                Node::Object(obj) => obj.upgrade().map(Handle::Object),
+                Node::Frame(frame) => frame.upgrade().map(Handle::Frame),
+                Node::Scope(scope) => scope.upgrade().map(Handle::Scope),
+                Node::Cell(cell) => cell.upgrade().map(Handle::Cell),
+            };
+            handle.map(|handle| live.push(handle)).is_some()
+        });
+
+        live
+    }
+
+    fn collect(&mut self) {
+        let live = self.live();
+        let index: HashMap<usize, usize> = live
+            .iter()
+            .enumerate()
+            .map(|(i, handle)| (handle.key(), i))
+            .collect();
+
+        // Upgrading each cell in `live` added one reference of our own
+        let mut external: Vec<isize> = live
+            .iter()
+            .map(|handle| handle.strong_count() as isize - 1)
+            .collect();
+        let mut edges = Vec::with_capacity(live.len());
+        for handle in &live {
+            let children = handle.children();
+            for child in children.iter().flatten() {
+                if let Some(&i) = index.get(child) {
+                    external[i] -= 1;
+                }
+            }
+            edges.push(children);
+        }
+
+        // Cells that can't be inspected right now are in use, so they count as roots
+        let mut marked = vec![false; live.len()];
+        let mut pending: Vec<usize> = (0..live.len())
+            .filter(|&i| external[i] > 0 || edges[i].is_none())
+            .collect();
+        while let Some(i) = pending.pop() {
+            if std::mem::replace(&mut marked[i], true) {
+                continue;
+            }
+            for child in edges[i].iter().flatten() {
+                if let Some(&j) = index.get(child) {
+                    pending.push(j);
+                }
+            }
+        }
+
+        let mut survivors = 0;
+        for (handle, marked) in live.iter().zip(&marked) {
+            if *marked {
+                survivors += 1;
+            } else {
+                handle.clear();
+            }
+        }
+
+        self.collections += 1;
+        self.since_collection = 0;
+        let grown = (survivors as f64 * self.config.growth_factor) as usize;
+        self.threshold = grown.max(self.config.initial_threshold);
+    }
+
+    fn stats(&mut self) -> GcStats {
+        let live_bytes = self.live().iter().map(Handle::size).sum();
+
+        GcStats {
+            allocations: self.allocations,
+            collections: self.collections,
+            live_bytes,
+        }
+    }
+}
+
+pub fn track_object(obj: &Rc<RefCell<Object>>) {
+    HEAP.with_borrow_mut(|heap| heap.track(Node::Object(Rc::downgrade(obj))));
+}
+
+pub fn track_frame(frame: &Rc<Frame>) {
+    HEAP.with_borrow_mut(|heap| heap.track(Node::Frame(Rc::downgrade(frame))));
+}
+
+pub fn track_cell(cell: &Rc<RefCell<Option<Value>>>) {
+    HEAP.with_borrow_mut(|heap| heap.track(Node::Cell(Rc::downgrade(cell))));
+}
+
+pub fn track_scope(scope: &Rc<Scope>) {
+    HEAP.with_borrow_mut(|heap| heap.track(Node::Scope(Rc::downgrade(scope))));
+}
+
+/// Replaces the collector's tunables, taking effect from the next allocation
+pub fn configure(config: GcConfig) {
+    HEAP.with_borrow_mut(|heap| {
+        heap.config = config;
+        heap.threshold = config.initial_threshold;
+    });
+}
+
+pub fn collect() {
+    HEAP.with_borrow_mut(Heap::collect);
+}
+
+pub fn stats() -> GcStats {
+    HEAP.with_borrow_mut(Heap::stats)
+}
+
+#[test]
+fn collects_unreachable_cycles() {
+    let list = Object::list(vec![]);
+    let weak = Rc::downgrade(&list);
+    let value = Value::Object(list.clone());
+    if let Object::List(values) = &mut *list.borrow_mut() {
+        values.push(value);
+    }
+
+    collect();
+    assert!(weak.upgrade().is_some(), "a rooted cycle must survive");
+
+    drop(list);
+    assert!(weak.upgrade().is_some());
+    collect();
+    assert!(
+        weak.upgrade().is_none(),
+        "an unreachable cycle must be freed"
+    );
+    assert!(stats().collections >= 2);
+
+    // A tree-walk function stored in the scope it closes over
+    let scope = Scope::new(None);
+    let weak = Rc::downgrade(&scope);
+    let fnc = crate::repr::fnc::UserFnc::new(
+        String::from("f"),
+        vec![],
+        crate::repr::types::Type::Any,
+        vec![],
+        Some(scope.clone()),
+    );
+    scope.declare("f", false, Some(Value::from(fnc))).unwrap();
+
+    collect();
+    assert!(weak.upgrade().is_some(), "a scope in use must survive");
+
+    drop(scope);
+    collect();
+    assert!(
+        weak.upgrade().is_none(),
+        "a scope only its functions hold must be freed"
+    );
+}
@@ -1,26 +1,39 @@
-use std::{fmt::Display, time::Instant};
-
-use uuid::Uuid;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    rc::Rc,
+    time::Instant,
+};
 
 use crate::{
-    error::{InterpretError, MemoryError, ParseError, UmpteenError},
+    boxed,
+    error::{InterpretError, MemoryError, UmpteenError},
     repr::{
         ast::{
-            expr::Expr,
+            expr::{Expr, Segment},
             ops::{Binary, Unary},
-            stmt::Stmt,
+            stmt::{Stmt, StmtKind, Variant},
         },
-        fnc::{Call, UserFnc},
+        enumeration::EnumType,
+        fnc::{check_arity, Call, Closure, Fnc, NativeFnc, UserFnc},
+        map::{Key, Map},
         object::Object,
+        range::Range,
+        record::RecordType,
         token::Token,
         value::Value,
     },
 };
 
 use super::{
-    env::{Env, Memory},
+    compiler::Compiler,
+    env::{Env, Scope},
     lexer::Lexer,
+    memory::Memory,
     parse::{Ast, Parser},
+    resolver::Resolver,
+    serialize,
+    vm::Vm,
 };
 
 #[derive(Debug)]
@@ -44,27 +57,118 @@ impl Display for Divergence {
     }
 }
 
+/// How deeply calls may nest unless `Interpreter::set_max_depth` says otherwise
+pub const MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Backend {
+    #[default]
+    TreeWalk,
+    Bytecode,
+}
+
 #[derive(Debug)]
 pub struct Interpreter {
     env: Env,
+    globals: HashMap<String, usize>, // Declarations of each global so far, as seen by the resolver
+    enums: HashMap<String, Vec<Variant>>,
+    types: HashSet<String>,
+    memory: Memory,
+    backend: Backend,
+    depth: usize, // Calls in progress
+    max_depth: usize,
     start: Instant,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_backend(Backend::default())
+    }
+
+    pub fn with_backend(backend: Backend) -> Self {
         Self {
             env: Env::default(),
+            globals: NativeFnc::ALL.iter().map(|f| (f.name(), 1)).collect(),
+            enums: HashMap::new(),
+            types: HashSet::new(),
+            memory: Memory::new(),
+            backend,
+            depth: 0,
+            max_depth: MAX_DEPTH,
             start: Instant::now(),
         }
     }
 
+    /// Limits how deeply calls may nest before a program fails, rather than running out of stack
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Counts a call about to start, failing if that nests calls deeper than the limit
+    pub(crate) fn enter(&mut self) -> Result<(), InterpretError> {
+        if self.depth >= self.max_depth {
+            Err(InterpretError::CallDepthExceeded(self.max_depth))?
+        }
+        self.depth += 1;
+
+        Ok(())
+    }
+
+    pub(crate) fn leave(&mut self, calls: usize) {
+        self.depth -= calls;
+    }
+
     pub fn run(&mut self, src: &str) -> Result<Value, UmpteenError> {
-        let tokens = Self::scan(src);
-        let ast = Self::parse(tokens)?;
-        self.interpret(ast)
+        let tokens = Self::scan(src)?;
+        let mut ast = Self::parse(tokens)?;
+        self.resolve(&mut ast)?;
+        match self.backend {
+            Backend::TreeWalk => {
+                let result = self.interpret(ast);
+                // Declarations a failed run never reached are forgotten, so that later programs
+                // count the globals they shadow as the environment does
+                if result.is_err() {
+                    let env = &self.env;
+                    self.globals.retain(|name, declared| {
+                        *declared = env.declarations(name);
+                        *declared > 0
+                    });
+                }
+                result
+            }
+            Backend::Bytecode => {
+                let script = self.compile(&ast)?;
+                self.execute(script)
+            }
+        }
+    }
+
+    /// Compiles `src` into the portable `.umc` format
+    pub fn build(&mut self, src: &str) -> Result<Vec<u8>, UmpteenError> {
+        let tokens = Self::scan(src)?;
+        let mut ast = Self::parse(tokens)?;
+        self.resolve(&mut ast)?;
+        let script = self.compile(&ast)?;
+        Ok(serialize::save(&self.memory, &script)?)
     }
 
-    fn scan(src: &str) -> Vec<Token> {
+    /// Loads a program produced by `build`, replacing any previously compiled state
+    pub fn run_compiled(&mut self, bytes: &[u8]) -> Result<Value, UmpteenError> {
+        let (memory, script) = serialize::load(bytes)?;
+        self.memory = memory;
+        self.execute(script)
+    }
+
+    pub fn compile(&mut self, ast: &Ast) -> Result<Closure, UmpteenError> {
+        let script = Compiler::new(&mut self.memory).compile(ast)?;
+        Ok(script)
+    }
+
+    pub fn execute(&mut self, script: Closure) -> Result<Value, UmpteenError> {
+        Vm::new().run(self, script, &[])
+    }
+
+    fn scan(src: &str) -> Result<Vec<Token<'_>>, UmpteenError> {
         let lexer = Lexer::new(src);
         lexer.scan()
     }
@@ -75,84 +179,185 @@ impl Interpreter {
         Ok(ast)
     }
 
+    fn resolve(&mut self, ast: &mut Ast) -> Result<(), UmpteenError> {
+        // Globals are only committed once the whole program resolves
+        let mut globals = self.globals.clone();
+        let mut enums = self.enums.clone();
+        let mut types = self.types.clone();
+        Resolver::new(&mut globals, &mut enums, &mut types).resolve(ast)?;
+        self.globals = globals;
+        self.enums = enums;
+        self.types = types;
+        Ok(())
+    }
+
     fn interpret(&mut self, ast: Ast) -> Result<Value, UmpteenError> {
-        for stmt in ast {
-            match self.exec(&stmt) {
-                Ok(_) => (),
-                Err(UmpteenError::Divergence(Divergence::Return(value))) => return Ok(value),
-                Err(e) => Err(e)?,
-            };
+        let mut result = Value::Empty;
+
+        // The value of a trailing expression statement becomes the result of the script
+        for stmt in &ast {
+            result = Value::Empty;
+            if let StmtKind::Expr(expr) = &stmt.kind {
+                result = self.eval(expr).map_err(|e| e.located(stmt.line))?;
+            } else if let Some(divergence) = self.exec(stmt)? {
+                Err(UmpteenError::from(InterpretError::from(divergence)).located(stmt.line))?
+            }
         }
 
-        Ok(Value::Empty)
+        Ok(result)
+    }
+
+    fn exec(&mut self, stmt: &Stmt) -> Result<Option<Divergence>, UmpteenError> {
+        self.exec_kind(stmt).map_err(|e| e.located(stmt.line))
     }
 
-    fn exec(&mut self, stmt: &Stmt) -> Result<Value, UmpteenError> {
-        match stmt {
-            Stmt::Declare { name, init } => {
-                self.env.declare(name)?;
+    fn exec_kind(&mut self, stmt: &Stmt) -> Result<Option<Divergence>, UmpteenError> {
+        match &stmt.kind {
+            StmtKind::Declare {
+                name,
+                init,
+                mutable,
+            } => {
+                // The initializer is evaluated first so it still sees any shadowed binding
+                let value = match init {
+                    Some(expr) => Some(self.eval(expr)?),
+                    None => None,
+                };
 
-                if let Some(expr) = init {
-                    let value = self.eval(expr)?;
-                    self.env.assign(name, None, value)?;
-                }
+                self.env.declare(name, *mutable, value)?;
             }
-            Stmt::Expr(expr) => {
+            StmtKind::Expr(expr) => {
                 self.eval(expr)?;
             }
-            Stmt::Block(statements) => {
-                let mem_key = Some(self.env.new_enclosed());
-                self.exec_block(statements, mem_key)?;
+            StmtKind::Block(statements) => {
+                let scope = self.env.new_enclosed();
+                return self.exec_block(statements, Some(scope));
             }
-            Stmt::Condition {
+            StmtKind::Condition {
                 test,
                 then_branch,
                 else_branch,
             } => {
                 if self.eval(test)?.truthy() {
                     let then_scope = self.env.new_enclosed();
-                    self.exec_block(then_branch, Some(then_scope))?;
+                    return self.exec_block(then_branch, Some(then_scope));
                 } else if let Some(else_branch) = else_branch {
                     let else_scope = self.env.new_enclosed();
-                    self.exec_block(else_branch, Some(else_scope))?;
+                    return self.exec_block(else_branch, Some(else_scope));
                 }
             }
-            Stmt::Loop(body) => loop {
+            StmtKind::Loop(body) => loop {
                 let loop_scope = self.env.new_enclosed();
-                match self.exec_block(body, Some(loop_scope)) {
-                    Err(UmpteenError::Divergence(Divergence::Break)) => break,
-                    Err(UmpteenError::Divergence(Divergence::Continue)) => continue,
-                    x => x,
-                }?;
+                match self.exec_block(body, Some(loop_scope))? {
+                    Some(Divergence::Break) => break,
+                    Some(Divergence::Continue) | None => continue,
+                    divergence => return Ok(divergence),
+                }
             },
+            StmtKind::While { test, body } => {
+                while self.eval(test)?.truthy() {
+                    let loop_scope = self.env.new_enclosed();
+                    match self.exec_block(body, Some(loop_scope))? {
+                        Some(Divergence::Break) => break,
+                        Some(Divergence::Continue) | None => continue,
+                        divergence => return Ok(divergence),
+                    }
+                }
+            }
+            StmtKind::For {
+                index,
+                item,
+                iterable,
+                body,
+            } => {
+                let iterable = self.eval(iterable)?.iterable();
+                let mut position = 0;
+                while let Some(value) = iterable.iterate(position)? {
+                    let loop_scope = self.env.new_enclosed();
+                    if let Some(index) = index {
+                        self.bind_const(&loop_scope, index, Value::from(position as f64))?;
+                    }
+                    self.bind_const(&loop_scope, item, value)?;
+                    position += 1;
 
-            Stmt::Break => Err(Divergence::Break)?,
-            Stmt::Continue => Err(Divergence::Continue)?,
-            Stmt::Return(expr) => Err(Divergence::Return(self.eval(expr)?))?,
-            Stmt::Exit => Err(Divergence::Exit)?,
-            Stmt::Fnc { name, params, body } => {
-                let fnc = UserFnc::new(name.to_string(), params.to_owned(), body.clone());
-                self.env.declare(name)?;
-                self.env.assign(name, None, Value::from(fnc))?;
+                    match self.exec_block(body, Some(loop_scope))? {
+                        Some(Divergence::Break) => break,
+                        Some(Divergence::Continue) | None => continue,
+                        divergence => return Ok(divergence),
+                    }
+                }
+            }
+
+            StmtKind::Break => return Ok(Some(Divergence::Break)),
+            StmtKind::Continue => return Ok(Some(Divergence::Continue)),
+            StmtKind::Return(expr) => return Ok(Some(Divergence::Return(self.eval(expr)?))),
+            StmtKind::Exit => return Ok(Some(Divergence::Exit)),
+            StmtKind::Fnc {
+                name,
+                params,
+                returns,
+                body,
+            } => {
+                let fnc = UserFnc::new(
+                    name.to_string(),
+                    params.to_owned(),
+                    returns.clone(),
+                    body.clone(),
+                    self.env.current(),
+                );
+                self.env.declare(name, false, Some(Value::from(fnc)))?;
+            }
+            StmtKind::Type {
+                name,
+                fields,
+                methods,
+            } => {
+                let methods = methods
+                    .iter()
+                    .map(|method| {
+                        let fnc = UserFnc::new(
+                            format!("{}.{}", name, method.name),
+                            method.params.to_owned(),
+                            method.returns.clone(),
+                            method.body.clone(),
+                            self.env.current(),
+                        );
+                        (method.name.clone(), Fnc::User(fnc))
+                    })
+                    .collect();
+                let ty = RecordType {
+                    name: name.to_string(),
+                    fields: fields.to_owned(),
+                    methods,
+                };
+                self.env.declare(name, false, Some(Value::from(ty)))?;
+            }
+            StmtKind::Enum { name, variants } => {
+                let ty = EnumType {
+                    name: name.to_string(),
+                    variants: variants.to_owned(),
+                };
+                self.env.declare(name, false, Some(Value::from(ty)))?;
             }
         }
 
-        Ok(Value::Empty)
+        Ok(None)
     }
 
+    /// Executes `statements` in the given scope, stopping early at the first divergence
     pub fn exec_block(
         &mut self,
         statements: &Ast,
-        env_id: Option<Uuid>,
-    ) -> Result<(), UmpteenError> {
-        let mut res = Ok(());
-        let previous = self.env.set_current(env_id);
+        scope: Option<Rc<Scope>>,
+    ) -> Result<Option<Divergence>, UmpteenError> {
+        let mut res = Ok(None);
+        let previous = self.env.set_current(scope);
 
         for stmt in statements {
             match self.exec(stmt) {
-                Ok(_) => (),
-                Err(err) => {
-                    res = Err(err);
+                Ok(None) => (),
+                diverged => {
+                    res = diverged;
                     break;
                 }
             }
@@ -165,6 +370,18 @@ impl Interpreter {
     fn eval(&mut self, expr: &Expr) -> Result<Value, UmpteenError> {
         let result = match expr {
             Expr::Literal(value) => value.clone(),
+            Expr::Interpolation(segments) => {
+                let mut string = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Text(text) => string.push_str(text),
+                        Segment::Value(expr, spec) => {
+                            string.push_str(&spec.apply(&self.eval(expr)?))
+                        }
+                    }
+                }
+                Value::String(boxed!(string))
+            }
             Expr::List(expressions) => {
                 let mut values = vec![];
                 for expr in expressions {
@@ -172,6 +389,14 @@ impl Interpreter {
                 }
                 Value::Object(Object::list(values))
             }
+            Expr::Map(entries) => {
+                let mut map = Map::new();
+                for (key, value) in entries {
+                    let key = Key::try_from(&self.eval(key)?)?;
+                    map.insert(key, self.eval(value)?);
+                }
+                Value::Object(Object::map(map))
+            }
             Expr::UnOp { expr, op } => {
                 let value = self.eval(expr)?;
                 match op {
@@ -183,11 +408,11 @@ impl Interpreter {
                 let lhs = self.eval(left)?;
 
                 match op {
-                    Binary::Add => (lhs + self.eval(right)?)?,
-                    Binary::Subtract => (lhs - self.eval(right)?)?,
-                    Binary::Multiply => (lhs * self.eval(right)?)?,
-                    Binary::Divide => (lhs / self.eval(right)?)?,
-                    Binary::Modulo => (lhs % self.eval(right)?)?,
+                    Binary::Add
+                    | Binary::Subtract
+                    | Binary::Multiply
+                    | Binary::Divide
+                    | Binary::Modulo => lhs.arithmetic(self.eval(right)?, *op)?,
                     Binary::And => {
                         if lhs.truthy() {
                             self.eval(right)?
@@ -204,84 +429,40 @@ impl Interpreter {
                     }
                     Binary::Equality => Value::Boolean(lhs == self.eval(right)?),
                     Binary::Inequality => Value::Boolean(lhs != self.eval(right)?),
-                    Binary::GreaterThan => {
-                        let rhs = self.eval(right)?;
-                        match (&lhs, &rhs) {
-                            (Value::Number(a), Value::Number(b)) => Value::Boolean(a > b),
-
-                            _ => Err(ParseError::IllegalBinaryOperation(
-                                lhs.to_string(),
-                                rhs.to_string(),
-                                *op,
-                            ))?,
-                        }
-                    }
-                    Binary::GreaterOrEqual => {
-                        let rhs = self.eval(right)?;
-                        match (&lhs, &rhs) {
-                            (Value::Number(a), Value::Number(b)) => Value::Boolean(a >= b),
-
-                            _ => Err(ParseError::IllegalBinaryOperation(
-                                lhs.to_string(),
-                                rhs.to_string(),
-                                *op,
-                            ))?,
-                        }
-                    }
-                    Binary::LessThan => {
-                        let rhs = self.eval(right)?;
-                        match (&lhs, &rhs) {
-                            (Value::Number(a), Value::Number(b)) => Value::Boolean(a < b),
-
-                            _ => Err(ParseError::IllegalBinaryOperation(
-                                lhs.to_string(),
-                                rhs.to_string(),
-                                *op,
-                            ))?,
-                        }
-                    }
-                    Binary::LessOrEqual => {
-                        let rhs = self.eval(right)?;
-                        match (&lhs, &rhs) {
-                            (Value::Number(a), Value::Number(b)) => Value::Boolean(a <= b),
-
-                            _ => Err(ParseError::IllegalBinaryOperation(
-                                lhs.to_string(),
-                                rhs.to_string(),
-                                *op,
-                            ))?,
-                        }
-                    }
+                    Binary::Identity => Value::Boolean(lhs.is(&self.eval(right)?)),
+                    Binary::Membership => self.eval(right)?.contains(&lhs)?,
+                    Binary::GreaterThan
+                    | Binary::GreaterOrEqual
+                    | Binary::LessThan
+                    | Binary::LessOrEqual => lhs.compare(&self.eval(right)?, *op)?,
                 }
             }
-            Expr::Binding { name, index } => {
-                if let Some(expr) = index {
-                    let idx = self.eval(expr)?;
-                    if let Value::Number(num) = idx {
-                        self.env.get(name, Some(num as usize))?
-                    } else {
-                        Err(MemoryError::CannotIndexWith(name.to_string()))?
-                    }
-                } else {
-                    self.env.get(name, None)?
-                }
-            }
-            Expr::Assign { name, index, expr } => {
+            Expr::Binding { name, resolved } => self.env.get_at(*resolved, name)?,
+            Expr::Assign {
+                name,
+                expr,
+                resolved,
+            } => {
                 let value = self.eval(expr)?;
-                if let Some(expr) = index {
-                    let idx = self.eval(expr)?;
-                    if let Value::Number(num) = idx {
-                        self.env.assign(name, Some(num as usize), value)?;
-                        Value::Empty
-                    } else {
-                        Err(MemoryError::CannotIndexWith(name.to_string()))?
-                    }
-                } else {
-                    self.env.assign(name, None, value)?;
-                    Value::Empty
-                }
+                self.env.assign_at(*resolved, name, value)?;
+                Value::Empty
             }
             Expr::Grouping { expr } => self.eval(expr)?,
+            Expr::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let start = self.eval(start)?;
+                let end = self.eval(end)?;
+                let step = match step {
+                    Some(step) => self.eval(step)?,
+                    None => Value::Empty,
+                };
+                let range = Range::new(&start, &end, &step, *inclusive)?;
+                Value::Object(Object::range(range))
+            }
             Expr::Call {
                 callee,
                 args: call_args,
@@ -293,19 +474,100 @@ impl Interpreter {
                     args.push(self.eval(arg)?);
                 }
 
-                if let Value::Object(ref obj) = callee
-                    && let Object::Fnc(ref mut fnc) = *obj.borrow_mut()
-                {
-                    match fnc.call(self, &args) {
-                        Ok(value) => return Ok(value),
-                        Err(UmpteenError::Divergence(Divergence::Return(value))) => {
-                            return Ok(value)
-                        }
-                        Err(e) => Err(e)?,
+                let Value::Object(ref obj) = callee else {
+                    Err(InterpretError::TriedToCallNonFunction(callee.to_string()))?
+                };
+                // The function is cloned out so the call does not hold a borrow on its object
+                let mut fnc = match *obj.borrow() {
+                    Object::Fnc(ref fnc) => fnc.clone(),
+                    _ => Err(InterpretError::TriedToCallNonFunction(callee.to_string()))?,
+                };
+
+                check_arity(&fnc, args.len())?;
+                fnc.call(self, &args)?
+            }
+            Expr::Lambda {
+                params,
+                returns,
+                body,
+            } => {
+                let fnc = UserFnc::new(
+                    String::from("lambda"),
+                    params.to_owned(),
+                    returns.clone(),
+                    body.clone(),
+                    self.env.current(),
+                );
+                Value::from(fnc)
+            }
+            Expr::Match { subject, arms } => {
+                let subject = self.eval(subject)?;
+                let mut bound = vec![];
+                let Some(arm) = arms.iter().find(|arm| {
+                    bound.clear();
+                    arm.pattern.matches(&subject, &mut bound)
+                }) else {
+                    Err(InterpretError::NoMatch(subject.to_string()))?
+                };
+
+                let scope = self.env.new_enclosed();
+                for (name, value) in arm.pattern.bindings().into_iter().zip(bound) {
+                    self.bind_const(&scope, name, value)?;
+                }
+                self.eval_in(&arm.body, &scope)?
+            }
+            Expr::Get { object, field } => self.eval(object)?.get_field(field)?,
+            Expr::Set {
+                object,
+                field,
+                op,
+                expr,
+            } => {
+                let object = self.eval(object)?;
+                let value = match op {
+                    Some(op) => {
+                        let current = object.get_field(field)?;
+                        current.arithmetic(self.eval(expr)?, *op)?
                     }
+                    None => self.eval(expr)?,
+                };
+                object.set_field(field, value)?;
+                Value::Empty
+            }
+            Expr::Index { object, index } => self.eval(object)?.index(&self.eval(index)?)?,
+            Expr::Slice {
+                object,
+                start,
+                end,
+                step,
+            } => {
+                let object = self.eval(object)?;
+                let mut parts = vec![];
+                for part in [start, end, step] {
+                    parts.push(match part {
+                        Some(expr) => self.eval(expr)?,
+                        None => Value::Empty,
+                    });
                 }
-
-                Err(InterpretError::TriedToCallNonFunction(callee.to_string()))?
+                object.slice_by(&parts[0], &parts[1], &parts[2])?
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                op,
+                expr,
+            } => {
+                let mut object = self.eval(object)?;
+                let index = self.eval(index)?;
+                let value = match op {
+                    Some(op) => {
+                        let current = object.index(&index)?;
+                        current.arithmetic(self.eval(expr)?, *op)?
+                    }
+                    None => self.eval(expr)?,
+                };
+                object.set_index(&index, value)?;
+                Value::Empty
             }
         };
 
@@ -316,10 +578,33 @@ impl Interpreter {
         self.start
     }
 
-    pub fn new_context(&mut self) -> (Uuid, &mut Memory) {
-        let key = self.env.new_enclosed();
-        let mem = self.env.retrieve_mut(key).unwrap();
-        (key, mem)
+    pub(crate) fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub(crate) fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Creates a scope for a function call, enclosed by the scope the function was defined in
+    pub fn new_context(&mut self, parent: Option<Rc<Scope>>) -> Rc<Scope> {
+        Scope::new(parent)
+    }
+
+    /// Declares a mutable binding directly in `scope`, as used for parameters
+    pub fn bind(&mut self, scope: &Scope, name: &str, value: Value) -> Result<(), MemoryError> {
+        scope.declare(name, true, Some(value))
+    }
+
+    fn bind_const(&mut self, scope: &Scope, name: &str, value: Value) -> Result<(), MemoryError> {
+        scope.declare(name, false, Some(value))
+    }
+
+    pub fn eval_in(&mut self, expr: &Expr, scope: &Rc<Scope>) -> Result<Value, UmpteenError> {
+        let previous = self.env.set_current(Some(scope.clone()));
+        let result = self.eval(expr);
+        self.env.set_current(previous);
+        result
     }
 }
 
@@ -328,3 +613,430 @@ impl Default for Interpreter {
         Self::new()
     }
 }
+
+/// Runs `src` on both backends, checking each prints `expected`, and hands back the interpreters
+/// for any follow-up checks
+#[cfg(test)]
+fn assert_both(src: &str, expected: &str) -> [Interpreter; 2] {
+    [Backend::TreeWalk, Backend::Bytecode].map(|backend| {
+        let mut umpteen = Interpreter::with_backend(backend);
+        let value = umpteen.run(src).unwrap();
+        assert_eq!(value.to_string(), expected, "on {:?}", backend);
+        umpteen
+    })
+}
+
+#[test]
+fn returns_are_values_not_errors() {
+    let mut umpteen = Interpreter::new();
+    let src = "fnc twice(n: Number) -> Number { loop { return n * 2; } } twice(21);";
+    assert_eq!(umpteen.run(src).unwrap(), Value::from(42.0));
+
+    let stray = umpteen.run("return 1;");
+    let Err(UmpteenError::Located(err, _)) = stray else {
+        panic!("expected a located error, got {:?}", stray)
+    };
+    assert!(matches!(
+        *err,
+        UmpteenError::ResolveError(crate::error::ResolveError::IllegalDivergence(_))
+    ));
+}
+
+#[test]
+fn runtime_errors_carry_the_line_they_happened_on() {
+    for backend in [Backend::TreeWalk, Backend::Bytecode] {
+        let mut umpteen = Interpreter::with_backend(backend);
+        let src = "fnc second(xs: List) -> Number {
+            return xs[1];
+        }
+        second([1]);";
+        let err = umpteen.run(src);
+        assert!(
+            matches!(err, Err(UmpteenError::Located(_, line)) if line.line() == 2),
+            "expected an error on line 2, got {:?}",
+            err
+        );
+    }
+}
+
+#[test]
+fn closures_capture_defining_scope() {
+    let mut umpteen = Interpreter::new();
+    let src = "
+        fnc counter() -> Fnc {
+            var count = 0;
+            fnc next() -> Number { count += 1; return count; }
+            return next;
+        }
+        var a = counter(); var b = counter();
+        a(); a(); b();
+        [a(), b()];";
+    let expected = Value::Object(Object::list(vec![Value::from(3.0), Value::from(2.0)]));
+    assert_eq!(umpteen.run(src).unwrap(), expected);
+
+    let src =
+        "fnc peek() -> Number { return secret; } fnc caller() -> Number { var secret = 1; return peek(); } caller();";
+    assert!(umpteen.run(src).is_err());
+}
+
+#[test]
+fn closures_capture_each_pass_through_a_block() {
+    let src = "
+        var fs = [];
+        var i = 0;
+        while i < 3 { let j = i; fs.push(fnc() => j); i += 1; }
+        fnc counter() -> Fnc {
+            var c = 0;
+            fnc next() -> Number { c += 1; return c; }
+            return next;
+        }
+        let count = counter();
+        count(); count();
+        [fs[0](), fs[1](), fs[2](), count()];";
+    for mut umpteen in assert_both(src, "[0, 1, 2, 3]") {
+        let src = "var n = 0; while n < 2 { var x; if n == 0 { x = 1; } n += 1; x; }";
+        assert!(
+            umpteen.run(src).is_err(),
+            "a redeclared var must start out unset"
+        );
+    }
+}
+
+#[test]
+fn for_loops_bind_their_variables_each_iteration() {
+    let src = "
+        var fs = [];
+        for i, x in [1, 2, 3] {
+            var doubled = x * 2;
+            fs.push(fnc() => i * 100 + x * 10 + doubled);
+        }
+        [fs[0](), fs[1](), fs[2]()];";
+    assert_both(src, "[12, 124, 236]");
+}
+
+#[test]
+fn scopes_are_freed_with_the_closures_holding_them() {
+    let mut umpteen = Interpreter::new();
+    let before = super::env::live_scopes();
+    let src = "var last; for i in 0..1000 { last = fnc() => i; } last();";
+    assert_eq!(umpteen.run(src).unwrap(), Value::from(999.0));
+    assert!(super::env::live_scopes() - before < 10);
+
+    // Functions kept in the scope they close over form cycles, left to the collector
+    let src = "for i in 0..1000 { fnc f() -> Number { return i; } let g = fnc() => f(); g(); }";
+    umpteen.run(src).unwrap();
+    super::heap::collect();
+    assert!(super::env::live_scopes() - before < 10);
+
+    // The VM gives each pass new storage for its locals, so nothing outlives its pass either
+    super::heap::configure(super::heap::GcConfig {
+        initial_threshold: 64,
+        growth_factor: 2.0,
+    });
+    let live = |passes: usize| {
+        let src = format!(
+            "var n = 0;
+            while n < {} {{ fnc f() -> Number {{ return n; }} let g = fnc() => f(); g(); n += 1; }}
+            gc_stats()[\"live_bytes\"];",
+            passes
+        );
+        let mut umpteen = Interpreter::with_backend(Backend::Bytecode);
+        match umpteen.run(&src).unwrap() {
+            Value::Number(bytes) => bytes,
+            value => panic!("expected a byte count, got {}", value),
+        }
+    };
+    let (few, many) = (live(10), live(1000));
+    assert!(many < few * 2.0);
+}
+
+#[test]
+fn let_bindings_are_immutable() {
+    for backend in [Backend::TreeWalk, Backend::Bytecode] {
+        let mut umpteen = Interpreter::with_backend(backend);
+        assert_eq!(
+            umpteen.run("let a = 10; let a = a + 1; a;").unwrap(),
+            Value::from(11.0)
+        );
+        assert!(matches!(
+            umpteen.run("a = 30;"),
+            Err(UmpteenError::MemoryError(MemoryError::AssignToImmutable(_)))
+                | Err(UmpteenError::Located(..))
+        ));
+
+        umpteen.run("var b = 1;").unwrap();
+        assert!(umpteen.run("var b = 2;").is_err());
+        assert_eq!(umpteen.run("b = 3; b;").unwrap(), Value::from(3.0));
+    }
+}
+
+#[test]
+fn shadowed_globals_stay_bound_where_they_were_used() {
+    let src = "
+        let a = 1;
+        fnc f() -> Number { return a; }
+        fnc g() -> Number { return b; }
+        let a = 2;
+        let b = 3;
+        [f(), g(), a];";
+    assert_both(src, "[1, 3, 2]");
+}
+
+#[test]
+fn calls_check_annotated_types() {
+    for backend in [Backend::TreeWalk, Backend::Bytecode] {
+        let mut umpteen = Interpreter::with_backend(backend);
+        umpteen
+            .run("fnc half(n: Number) -> Number { return n / 2; }")
+            .unwrap();
+        assert_eq!(umpteen.run("half(4);").unwrap(), Value::from(2.0));
+
+        let err = umpteen.run(r#"half("4");"#).unwrap_err().to_string();
+        assert!(err.starts_with("parameter `n` of `half` expects Number, got String"));
+
+        umpteen.run("fnc nothing() -> Number {}").unwrap();
+        let err = umpteen.run("nothing();").unwrap_err().to_string();
+        assert!(err.starts_with("`nothing` should return Number, returned Empty"));
+    }
+}
+
+#[test]
+fn calls_check_arity_and_fill_defaults() {
+    for backend in [Backend::TreeWalk, Backend::Bytecode] {
+        let mut umpteen = Interpreter::with_backend(backend);
+        umpteen
+            .run("fnc add(a: Number, b: Number = a) -> Number { return a + b; }")
+            .unwrap();
+        assert_eq!(umpteen.run("add(2);").unwrap(), Value::from(4.0));
+        assert_eq!(umpteen.run("add(2, 3);").unwrap(), Value::from(5.0));
+
+        let err = umpteen.run("add(1, 2, 3);").unwrap_err().to_string();
+        assert!(err.starts_with("`add` expects 1 to 2 arguments, got 3"));
+        let err = umpteen.run("str();").unwrap_err().to_string();
+        assert!(err.starts_with("`str` expects 1 argument, got 0"));
+
+        umpteen
+            .run("fnc f(a: Number = \"x\") -> Number { return a; }")
+            .unwrap();
+        let err = umpteen.run("f();").unwrap_err().to_string();
+        assert!(err.starts_with("parameter `a` of `f` expects Number, got String"));
+    }
+}
+
+#[test]
+fn calls_nest_only_as_deep_as_the_limit() {
+    for backend in [Backend::TreeWalk, Backend::Bytecode] {
+        let mut umpteen = Interpreter::with_backend(backend);
+        umpteen.set_max_depth(16);
+        umpteen
+            .run("fnc down(n: Number) -> Number { return match n { 0 => 0, _ => down(n - 1) }; }")
+            .unwrap();
+        assert_eq!(umpteen.run("down(15);").unwrap(), Value::from(0.0));
+
+        let err = umpteen.run("down(16);").unwrap_err().to_string();
+        assert!(err.starts_with("calls nested more than 16 deep"));
+        assert!(umpteen.run("map([15], down);").is_ok());
+    }
+}
+
+#[test]
+fn objects_are_shared_by_reference() {
+    let src = "
+        fnc push(xs: List, x: Number) { xs[len(xs)] = x; }
+        var a = [1]; var b = a;
+        push(b, 2);
+        [a, a is b, a == [1, 2], a is [1, 2]];";
+    let expected = "[[1, 2], true, true, false]";
+    assert_both(src, expected);
+}
+
+#[test]
+fn cyclic_objects_compare_without_recursing_forever() {
+    let src = "
+        var a = []; a.push(a);
+        var b = []; b.push(b);
+        [a == a, a in [a], a == b, a is b, a == [[]]];";
+    let expected = "[true, true, true, false, false]";
+    assert_both(src, expected);
+}
+
+#[test]
+fn ranges_drive_loops_slices_and_membership() {
+    let src = "
+        var total = 0;
+        for i in 0..=10 by 5 { total += i; }
+        let xs = [1, 2, 3, 4];
+        [total, xs[1..3], xs[2..1e18], xs[3..0 by -2], 2 in 0..4, 4 in 0..4,
+            len(10..0 by -3), str(0..1)];";
+    let expected = "[15, [2, 3], [3, 4], [4, 2], true, false, 4, 0..1]";
+    for mut umpteen in assert_both(src, expected) {
+        assert!(umpteen.run("len(0..1e300);").is_err());
+    }
+}
+
+#[test]
+fn maps_index_by_key_in_insertion_order() {
+    let src = "
+        var m = {\"b\": 1, 2: \"two\"};
+        m[\"a\"] = 3; m[\"b\"] += 1;
+        remove(m, 2);
+        [m, keys(m), m[\"b\"], has(m, \"a\"), 2 in m];";
+    let expected = "[{b: 2, a: 3}, [b, a], 2, true, false]";
+    assert_both(src, expected);
+}
+
+#[test]
+fn records_compare_fields_structurally() {
+    let src = "
+        type Point { x: Number, y: Number }
+        var p = Point(1, 2);
+        p.x = 3; p.y += 1;
+        [p, p.x, p == Point(3, 3), p == Point(3, 4)];";
+    let expected = "[Point { x: 3, y: 3 }, 3, true, false]";
+    for mut umpteen in assert_both(src, expected) {
+        assert!(umpteen.run("p.x = \"three\";").is_err());
+    }
+}
+
+#[test]
+fn match_destructures_values_and_enum_variants() {
+    let src = "
+        enum Shape { Circle(Number), Rect(Number, Number), Dot }
+        fnc area(s: Shape) -> Number {
+            return match s { Shape.Circle(r) => 3 * r * r, Shape.Rect(w, h) => w * h, Shape.Dot => 0 };
+        }
+        var out = [area(Shape.Rect(2, 3)), area(Shape.Dot)];
+        for x in [-2, 7, [1, 2, 3], \"a\"] {
+            out[len(out)] = match x { -5..0 => \"neg\", 0..10 => \"small\", [a, ..rest] => rest, _ => x };
+        }
+        out;";
+    let expected = "[6, 0, neg, small, [2, 3], a]";
+    for mut umpteen in assert_both(src, expected) {
+        assert!(umpteen.run("match Shape.Dot { Shape.Dot => 0 };").is_err());
+        assert!(umpteen.run("match 3 { 1 => 1 };").is_err());
+    }
+}
+
+#[test]
+fn failed_patterns_bind_nothing() {
+    let src = "
+        enum E { P(Number, Number) }
+        fnc pair(a: Number, b: Number) -> List { return [a, b]; }
+        [pair(match [1, 2] { [a, 3] => a, _ => 0 }, 5),
+            pair(match E.P(1, 2) { E.P(x, 3) => x, E.P(x, y) => x + y }, 5)];";
+    assert_both(src, "[[0, 5], [3, 5]]");
+}
+
+#[test]
+fn lambdas_are_values_passed_to_natives() {
+    let src = "
+        fnc adder(n: Number) -> Fnc { return fnc(x: Number) => x + n; }
+        let evens = filter(0..10, fnc(x) { return x % 2 == 0; });
+        let handlers = [fnc() => \"called\"];
+        [map(evens, adder(1)), handlers[0]()];";
+    let expected = "[[1, 3, 5, 7, 9], called]";
+    for mut umpteen in assert_both(src, expected) {
+        assert!(umpteen.run("map([1], fnc(x: String) => x);").is_err());
+    }
+}
+
+#[test]
+fn methods_receive_their_value_as_self() {
+    let src = "
+        type Point {
+            x: Number,
+            y: Number,
+
+            fnc scale(self, k: Number) -> Point { return Point(self.x * k, self.y * k); }
+        }
+        var xs = [1];
+        xs.push(Point(1, 2).scale(3).x);
+        [xs, xs.len(), \" a,b \".trim().upper().split(\",\")];";
+    let expected = "[[1, 3], 2, [A, B]]";
+    for mut umpteen in assert_both(src, expected) {
+        assert!(umpteen.run("push([], 1);").is_err());
+        assert!(umpteen.run("(1).push(2);").is_err());
+    }
+}
+
+#[test]
+fn any_expression_can_be_indexed_and_assigned_through() {
+    let src = "
+        var calls = 0;
+        fnc first() -> Number { calls += 1; return 0; }
+        let grid = [[1, 2], [3, 4]];
+        grid[1][0] = 9;
+        grid[first()][1] += 10;
+        [grid, calls, [5, 6][1], \"abc\"[2]];";
+    let expected = "[[[1, 12], [9, 4]], 1, 6, c]";
+    assert_both(src, expected);
+}
+
+#[test]
+fn compound_assignment_reads_the_target_before_the_value() {
+    let src = "
+        type Counter { n: Number }
+        let c = Counter(1);
+        let xs = [1];
+        fnc field() -> Number { c.n = 100; return 1; }
+        fnc index() -> Number { xs[0] = 100; return 1; }
+        c.n += field();
+        xs[0] += index();
+        [c.n, xs[0]];";
+    assert_both(src, "[2, 2]");
+}
+
+#[test]
+fn indexes_are_bounds_checked_and_slices_copy() {
+    let src = "
+        let xs = [0, 1, 2, 3, 4];
+        let ys = [1]; ys[1] = 2;
+        [xs[-1], xs[1:3], xs[::-2], xs[-2:], \"abc\"[::-1], xs[:2] is xs, ys];";
+    let expected = "[4, [1, 2], [4, 2, 0], [3, 4], cba, false, [1, 2]]";
+    for mut umpteen in assert_both(src, expected) {
+        for src in [
+            "[1][1];",
+            "[1][-2];",
+            "[1][0.5];",
+            "[1][::0];",
+            "ys[3] = 0;",
+        ] {
+            assert!(umpteen.run(src).is_err(), "`{}` should fail", src);
+        }
+        let err = umpteen.run("ys[1e20] = 0;").unwrap_err().to_string();
+        assert!(err.starts_with("index 100000000000000000000 is out of bounds for length 2"));
+    }
+}
+
+#[test]
+fn strings_are_measured_in_characters() {
+    let src = "
+        let s = \"h\u{e9}llo \u{2615}\";
+        [len(s), s[1], s[-1], ord(s[-1]), chr(233), \"e\u{301}\".graphemes().len()];";
+    let expected = "[7, \u{e9}, \u{2615}, 9749, \u{e9}, 1]";
+    for mut umpteen in assert_both(src, expected) {
+        for src in ["chr(55296);", "chr(-1);", "ord(\"\");", "ord(\"ab\");"] {
+            assert!(umpteen.run(src).is_err(), "`{}` should fail", src);
+        }
+    }
+}
+
+#[test]
+fn strings_and_maps_iterate_in_order() {
+    let src = "
+        var out = [];
+        for i, c in \"h\u{e9}y\" { out.push(str(i) + c); }
+        for k in {\"b\": 1, \"a\": 2} { out.push(k); }
+        [out, map(\"ab\", fnc(c) => c + c)];";
+    assert_both(src, "[[0h, 1\u{e9}, 2y, b, a], [aa, bb]]");
+}
+
+#[test]
+fn strings_interpolate_formatted_values() {
+    let src = "
+        let x = -2.5;
+        let xs = [1, 2];
+        \"{{x}} = {x} {x:.2} [{x:07.1}] [{\"ab\":-^6}] {xs[0] + xs[1]} {\"in{xs[1:]}\"}\";";
+    let expected = "{x} = -2.5 -2.50 [-0002.5] [--ab--] 3 in[2]";
+    assert_both(src, expected);
+}
@@ -1,18 +1,26 @@
 use std::{iter::Peekable, str::Chars};
 
 use crate::{
-    error::Line,
-    repr::token::{Token, TokenType},
-    util::report_line,
+    boxed,
+    error::{LexError, Line, UmpteenError},
+    repr::token::{Span, Token, TokenType},
 };
 
+/// An open `{...}` of an interpolated string, to be continued once it closes
+struct Hole {
+    depth: usize, // Brackets opened inside the hole so far
+    multiline: bool,
+    opened: Line,
+    start: usize, // Offset just past the `{`, where the interpolated value begins
+}
+
 pub struct Lexer<'s> {
     source: &'s str,
     chars: Peekable<Chars<'s>>,
-    line: Line,
-    offset: usize,
+    line: Line,    // The line, and the number of characters read from it so far
+    offset: usize, // In bytes, so that lexemes can be sliced out of the source
+    holes: Vec<Hole>,
     finished: bool,
-    previous: TokenType,
 }
 
 impl<'s> Lexer<'s> {
@@ -24,25 +32,32 @@ impl<'s> Lexer<'s> {
             chars,
             line: Line::new(1),
             offset: 0,
+            holes: vec![],
             finished: false,
-            previous: TokenType::Eof,
         }
     }
 
-    pub fn scan(mut self) -> Vec<Token<'s>> {
+    pub fn scan(mut self) -> Result<Vec<Token<'s>>, UmpteenError> {
         let mut tokens = vec![];
         while !self.finished {
-            if let Some(token) = self.scan_token() {
+            if let Some(token) = self.scan_token()? {
                 tokens.push(token);
             }
         }
-        self.line.newline();
-        tokens.push(Token::new(TokenType::Eof, "<EOF>", self.line));
+        if let Some(hole) = self.holes.first() {
+            Err(located(LexError::UnterminatedString, hole.opened))?
+        }
+        let end = Span {
+            start: self.offset,
+            end: self.offset,
+        };
+        let line = Line::at(self.line.line(), self.line.column() + 1);
+        tokens.push(Token::new(TokenType::Eof, "<EOF>", end, line));
 
         #[cfg(debug_assertions)]
         dbg!(&tokens);
 
-        tokens
+        Ok(tokens)
     }
 
     fn peek(&mut self) -> Option<char> {
@@ -65,19 +80,30 @@ impl<'s> Lexer<'s> {
         }
     }
 
+    // The line and column of the next character
+    fn position(&self) -> Line {
+        Line::at(self.line.line(), self.line.column() + 1)
+    }
+
     fn advance(&mut self) -> Option<char> {
-        self.line.advance();
-        self.offset += 1;
-        self.chars.next()
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        match c {
+            '\n' => self.line.newline(),
+            _ => self.line.advance(),
+        };
+
+        Some(c)
     }
 
-    fn scan_token(&mut self) -> Option<Token<'s>> {
+    fn scan_token(&mut self) -> Result<Option<Token<'s>>, UmpteenError> {
         if self.peek().is_none() {
             self.finished = true;
-            return None;
+            return Ok(None);
         }
 
         let start = self.offset;
+        let line = self.position();
 
         let c = self.advance().unwrap();
 
@@ -99,25 +125,63 @@ impl<'s> Lexer<'s> {
 
         macro_rules! token {
             ($t:tt, $lx:expr) => {
-                Token::new(TokenType::$t, $lx, self.line)
+                Token::new(
+                    TokenType::$t,
+                    $lx,
+                    Span {
+                        start,
+                        end: self.offset,
+                    },
+                    line,
+                )
             };
             ($t:tt) => {
-                Token::new(TokenType::$t, lexeme!(), self.line)
+                token!($t, lexeme!())
             };
         }
 
-        macro_rules! last {
-            ($first:tt$(,$rest:tt)+) => {{
-                self.previous == TokenType::$first$(|| self.previous == TokenType::$rest)+
-            }};
-        }
-
         let tk = match c {
-            '\n' => {
-                self.line.newline();
-                return None;
+            c if c.is_whitespace() => return Ok(None),
+
+            '(' | '{' | '[' if !self.holes.is_empty() => {
+                self.holes.last_mut().unwrap().depth += 1;
+                match c {
+                    '(' => token!(LeftParen),
+                    '{' => token!(LeftBrace),
+                    _ => token!(LeftBracket),
+                }
+            }
+            ')' | '}' | ']' if self.holes.last().is_some_and(|hole| hole.depth > 0) => {
+                self.holes.last_mut().unwrap().depth -= 1;
+                match c {
+                    ')' => token!(RightParen),
+                    '}' => token!(RightBrace),
+                    _ => token!(RightBracket),
+                }
+            }
+            '}' if !self.holes.is_empty() => {
+                let hole = self.holes.pop().unwrap();
+                if self.source[hole.start..start].trim().is_empty() {
+                    Err(located(LexError::EmptyInterpolation, line))?
+                }
+                return Ok(Some(self.string(
+                    start,
+                    line,
+                    hole.opened,
+                    hole.multiline,
+                    false,
+                )?));
+            }
+            ':' if self.holes.last().is_some_and(|hole| hole.depth == 0) => {
+                let hole = self.holes.last().unwrap();
+                if self.source[hole.start..start].trim().is_empty() {
+                    Err(located(LexError::EmptyInterpolation, line))?
+                }
+                while self.peek().is_some_and(|c| c != '}' && c != '"') {
+                    self.advance();
+                }
+                token!(FormatSpec, lexeme!(start + 1, self.offset))
             }
-            c if c.is_whitespace() => return None,
 
             '(' => token!(LeftParen),
             ')' => token!(RightParen),
@@ -133,9 +197,6 @@ impl<'s> Lexer<'s> {
                     self.advance();
                     self.advance();
                     while let Some(c) = self.peek() {
-                        if c == '\n' {
-                            self.line.newline();
-                        }
                         if c == '#' {
                             self.advance();
                             if matches!((self.peek(), self.peek_ahead(1)), (Some('#'), Some('#'))) {
@@ -147,7 +208,7 @@ impl<'s> Lexer<'s> {
                             self.advance();
                         }
                     }
-                    return None;
+                    return Ok(None);
                 } else {
                     while let Some(c) = self.peek() {
                         if c == '\n' {
@@ -155,7 +216,7 @@ impl<'s> Lexer<'s> {
                         }
                         self.advance();
                     }
-                    return None;
+                    return Ok(None);
                 }
             }
 
@@ -228,6 +289,16 @@ impl<'s> Lexer<'s> {
                 }
             }
 
+            '.' => {
+                if !self.catch('.') {
+                    token!(Dot)
+                } else if self.catch('=') {
+                    token!(DotDotEqual)
+                } else {
+                    token!(DotDot)
+                }
+            }
+
             '&' if self.catch('&') => {
                 token!(And)
             }
@@ -236,44 +307,19 @@ impl<'s> Lexer<'s> {
             }
 
             '"' => {
-                let mut end: usize = self.offset;
-                while self.peek().is_some() {
-                    end = self.offset;
-                    let c = self.advance().unwrap();
-                    if c == '\\' {
-                        self.advance();
-                    }
-                    if c == '"' {
-                        break;
-                    }
-                }
-                token!(String, lexeme!(start + 1, end))
+                let multiline = self.peek() == Some('"') && self.peek_ahead(1) == Some('"');
+                self.open_string(multiline);
+                self.string(start, line, line, multiline, true)?
             }
-
-            c if c.is_ascii_digit() => {
-                let mut end = self.offset;
-                macro_rules! digits {
-                    () => {
-                        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
-                            self.advance().unwrap();
-                            end = self.offset;
-                        }
-                    };
-                }
-
-                digits!();
-
-                // Matches a decimal point and at least one additional digit
-                if matches!((self.peek(), self.peek_ahead(1)), (Some('.'), Some(c)) if c.is_ascii_digit())
-                {
-                    self.advance(); // Skip the decimal point
-                    digits!()
-                }
-
-                let lx = lexeme!(end);
-                token!(Number, lx)
+            'r' if self.peek() == Some('"') => {
+                self.advance();
+                let multiline = self.peek() == Some('"') && self.peek_ahead(1) == Some('"');
+                self.open_string(multiline);
+                self.raw_string(start, line, multiline)?
             }
 
+            c if c.is_ascii_digit() => self.number(c, start, line)?,
+
             c if is_identic(c) => {
                 let mut end: usize = self.offset;
                 while self.peek().is_some_and(is_identic) {
@@ -293,28 +339,351 @@ impl<'s> Lexer<'s> {
                     "if" => token!(If, lx),
                     "else" => token!(Else, lx),
                     "loop" => token!(Loop, lx),
+                    "while" => token!(While, lx),
+                    "for" => token!(For, lx),
+                    "in" => token!(In, lx),
+                    "by" => token!(By, lx),
                     "break" => token!(Break, lx),
                     "continue" => token!(Continue, lx),
                     "fnc" => token!(Fnc, lx),
                     "return" => token!(Return, lx),
+                    "type" => token!(Type, lx),
+                    "enum" => token!(Enum, lx),
+                    "match" => token!(Match, lx),
+                    "is" => token!(Is, lx),
 
-                    _ => {
-                        if last!(ThinArrow, Colon) {
-                            token!(TypeName, lx)
-                        } else {
-                            token!(Identifier, lx)
-                        }
+                    _ => token!(Identifier, lx),
+                }
+            }
+
+            c => Err(located(LexError::UnexpectedSymbol(c), line))?,
+        };
+
+        Ok(Some(tk))
+    }
+
+    // Reads a numeric literal that starts with `first`, leaving its value as the lexeme
+    fn number(&mut self, first: char, start: usize, line: Line) -> Result<Token<'s>, UmpteenError> {
+        let radix = match (first, self.peek()) {
+            ('0', Some('x')) => 16,
+            ('0', Some('o')) => 8,
+            ('0', Some('b')) => 2,
+            _ => 10,
+        };
+        let missing = |lexer: &Self| {
+            let prefix = lexer.source[start..lexer.offset].to_string();
+            located(LexError::MissingDigits(prefix), line)
+        };
+
+        let mut text = String::new();
+        if radix != 10 {
+            self.advance();
+            match self.peek() {
+                Some(c) if c.is_digit(radix) => (),
+                Some('_') => Err(located(LexError::MisplacedSeparator, self.position()))?,
+                Some(c) if c.is_alphanumeric() => {
+                    Err(located(LexError::InvalidDigit(c, radix), self.position()))?
+                }
+                _ => Err(missing(self))?,
+            }
+            text.push(self.advance().unwrap());
+            self.digits(&mut text, radix)?;
+        } else {
+            text.push(first);
+            self.digits(&mut text, radix)?;
+
+            // Matches a decimal point and at least one additional digit
+            if matches!((self.peek(), self.peek_ahead(1)), (Some('.'), Some(c)) if c.is_ascii_digit())
+            {
+                self.advance();
+                text.push('.');
+                text.push(self.advance().unwrap());
+                self.digits(&mut text, radix)?;
+            }
+
+            if self.catch('e') || self.catch('E') {
+                text.push('e');
+                if let Some(sign @ ('+' | '-')) = self.peek() {
+                    self.advance();
+                    text.push(sign);
+                }
+                if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    Err(missing(self))?
+                }
+                text.push(self.advance().unwrap());
+                self.digits(&mut text, radix)?;
+            }
+        }
+
+        if let Some(c) = self.peek().filter(|c| c.is_alphanumeric()) {
+            Err(located(LexError::InvalidDigit(c, radix), self.position()))?
+        }
+
+        let lexeme = &self.source[start..self.offset];
+        let out_of_range = || located(LexError::NumberOutOfRange(lexeme.to_string()), line);
+        let value = match radix {
+            10 => Some(text.parse::<f64>().unwrap()).filter(|x| x.is_finite()),
+            _ => u64::from_str_radix(&text, radix).ok().map(|x| x as f64),
+        };
+        let value = value.ok_or_else(out_of_range)?;
+
+        let span = Span {
+            start,
+            end: self.offset,
+        };
+        Ok(Token::new(TokenType::Number, value.to_string(), span, line))
+    }
+
+    // Reads any further digits, along with the single `_` separators between them
+    fn digits(&mut self, text: &mut String, radix: u32) -> Result<(), UmpteenError> {
+        loop {
+            match self.peek() {
+                Some('_') => {
+                    let at = self.position();
+                    self.advance();
+                    if !self.peek().is_some_and(|c| c.is_digit(radix)) {
+                        Err(located(LexError::MisplacedSeparator, at))?
                     }
                 }
+                Some(c) if c.is_digit(radix) => {
+                    self.advance();
+                    text.push(c);
+                }
+                _ => return Ok(()),
             }
+        }
+    }
+
+    // Skips the rest of an opening `"""`, along with the line break that directly follows it
+    fn open_string(&mut self, multiline: bool) {
+        if multiline {
+            self.advance();
+            self.advance();
+            self.catch('\r');
+            self.catch('\n');
+        }
+    }
+
+    // Reaches the end of a string literal, if the next character closes it
+    fn close_string(&mut self, c: char, multiline: bool) -> bool {
+        if c != '"' {
+            return false;
+        }
+        if !multiline {
+            return true;
+        }
+        if self.peek() == Some('"') && self.peek_ahead(1) == Some('"') {
+            self.advance();
+            self.advance();
+            return true;
+        }
+        false
+    }
 
-            c => {
-                report_line(format!("Unexpected Symbol `{}`", c), self.line);
-                None?
+    // Decodes string text up to the closing quote, or up to the `{` of an interpolated value.
+    // `opened` is where the literal began, as it may be `opening` or continue after an interpolation
+    fn string(
+        &mut self,
+        start: usize,
+        line: Line,
+        opened: Line,
+        multiline: bool,
+        opening: bool,
+    ) -> Result<Token<'s>, UmpteenError> {
+        let mut text = String::new();
+        let mut hole = false;
+        loop {
+            let at = self.position();
+            let Some(c) = self.advance() else {
+                Err(located(LexError::UnterminatedString, opened))?
+            };
+            match c {
+                c if self.close_string(c, multiline) => break,
+                '\n' if !multiline => Err(located(LexError::UnterminatedString, opened))?,
+                '\\' => text.push(self.escape(at)?),
+                '{' | '}' if self.peek() == Some(c) => {
+                    self.advance();
+                    text.push(c);
+                }
+                '{' => {
+                    hole = true;
+                    break;
+                }
+                '}' => Err(located(LexError::UnmatchedBrace, at))?,
+                c => text.push(c),
             }
+        }
+
+        let kind = match (opening, hole) {
+            (true, false) => TokenType::String,
+            (true, true) => TokenType::StringStart,
+            (false, true) => TokenType::StringMiddle,
+            (false, false) => TokenType::StringEnd,
+        };
+        if hole {
+            self.holes.push(Hole {
+                depth: 0,
+                multiline,
+                opened,
+                start: self.offset,
+            });
+        }
+
+        let span = Span {
+            start,
+            end: self.offset,
         };
+        Ok(Token::new(kind, text, span, line))
+    }
+
+    // Takes string text exactly as written, without escapes or interpolation
+    fn raw_string(
+        &mut self,
+        start: usize,
+        line: Line,
+        multiline: bool,
+    ) -> Result<Token<'s>, UmpteenError> {
+        let text = self.offset;
+        let mut end = self.offset;
+        loop {
+            let Some(c) = self.advance() else {
+                Err(located(LexError::UnterminatedString, line))?
+            };
+            match c {
+                c if self.close_string(c, multiline) => break,
+                '\n' if !multiline => Err(located(LexError::UnterminatedString, line))?,
+                _ => end = self.offset,
+            }
+        }
+
+        let span = Span {
+            start,
+            end: self.offset,
+        };
+        let lexeme = &self.source[text..end];
+        Ok(Token::new(TokenType::String, lexeme, span, line))
+    }
+
+    // Decodes the escape sequence after a backslash found at `at`
+    fn escape(&mut self, at: Line) -> Result<char, UmpteenError> {
+        let from = self.offset - 1;
+        let invalid = |lexer: &Self| {
+            let sequence = lexer.source[from..lexer.offset].to_string();
+            located(LexError::InvalidEscape(sequence), at)
+        };
+
+        let c = match self.advance() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('\\') => '\\',
+            Some('x') => {
+                let mut code = 0;
+                for _ in 0..2 {
+                    match self.peek().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => code = code * 16 + digit,
+                        None => Err(invalid(self))?,
+                    }
+                    self.advance();
+                }
+                match char::from_u32(code) {
+                    Some(c) if c.is_ascii() => c,
+                    _ => Err(invalid(self))?,
+                }
+            }
+            Some('u') => {
+                if !self.catch('{') {
+                    Err(invalid(self))?
+                }
+                let mut code: u32 = 0;
+                let mut digits = 0;
+                while let Some(digit) = self.peek().and_then(|c| c.to_digit(16)) {
+                    self.advance();
+                    code = code.saturating_mul(16).saturating_add(digit);
+                    digits += 1;
+                }
+                if !self.catch('}') || !(1..=6).contains(&digits) {
+                    Err(invalid(self))?
+                }
+                char::from_u32(code).ok_or_else(|| invalid(self))?
+            }
+            Some('\n') | None => Err(located(LexError::UnterminatedString, at))?,
+            Some(_) => Err(invalid(self))?,
+        };
+
+        Ok(c)
+    }
+}
+
+fn located(error: LexError, line: Line) -> UmpteenError {
+    UmpteenError::Located(boxed!(error.into()), line)
+}
+
+#[test]
+fn spans_slice_unicode_source_exactly() {
+    let source = "# ☕\nlet café = \"wörld\";";
+    let tokens = Lexer::new(source).scan().unwrap();
+
+    let texts: Vec<_> = tokens
+        .iter()
+        .map(|tk| &source[tk.span.start..tk.span.end])
+        .collect();
+    assert_eq!(texts, ["let", "café", "=", "\"wörld\"", ";", ""]);
+    assert_eq!(tokens[3].lexeme, "wörld");
+    assert_eq!(tokens[2].line, Line::at(2, 10));
+}
+
+#[test]
+fn strings_decode_escapes_or_report_where_they_fail() {
+    let source = "\"a\\tb\\x41\\u{e9}\" r\"\\n{x}\" \"\"\"\nsay \"hi\" twice\"\"\"";
+    let tokens = Lexer::new(source).scan().unwrap();
+    let texts: Vec<_> = tokens.iter().map(|tk| &*tk.lexeme).collect();
+    assert_eq!(
+        texts,
+        ["a\tbA\u{e9}", "\\n{x}", "say \"hi\" twice", "<EOF>"]
+    );
+
+    for (source, at) in [("x = \"\\q\";", 6), ("\"\\u{d800}\"", 2), ("\"ab\nc\"", 1)] {
+        let Err(UmpteenError::Located(_, line)) = Lexer::new(source).scan() else {
+            panic!("`{}` should not lex", source);
+        };
+        assert_eq!(line, Line::at(1, at));
+    }
+}
+
+#[test]
+fn numbers_are_read_in_any_radix() {
+    let source = "0xFF 0o17 0b1010 1_000 1.5e-3 2E3";
+    let tokens = Lexer::new(source).scan().unwrap();
+    let texts: Vec<_> = tokens.iter().map(|tk| &*tk.lexeme).collect();
+    assert_eq!(
+        texts,
+        ["255", "15", "10", "1000", "0.0015", "2000", "<EOF>"]
+    );
+
+    for (source, at) in [("0b12", 4), ("1__0", 2), ("0x", 1), ("1e", 1), ("1e999", 1)] {
+        let Err(UmpteenError::Located(_, line)) = Lexer::new(source).scan() else {
+            panic!("`{}` should not lex", source);
+        };
+        assert_eq!(line, Line::at(1, at));
+    }
+}
 
-        self.previous = tk.kind;
-        Some(tk)
+#[test]
+fn interpolated_strings_reject_stray_and_empty_braces() {
+    let tokens = Lexer::new("\"{{}} {x}\"").scan().unwrap();
+    let texts: Vec<_> = tokens.iter().map(|tk| &*tk.lexeme).collect();
+    assert_eq!(texts, ["{} ", "x", "", "<EOF>"]);
+
+    for (source, error) in [
+        ("\"a}b\"", "unmatched `}` in string literal"),
+        ("\"{ }\"", "empty interpolation"),
+        ("\"{:>4}\"", "empty interpolation"),
+    ] {
+        let err = Lexer::new(source).scan().unwrap_err().to_string();
+        assert!(err.starts_with(error), "`{}` gave `{}`", source, err);
     }
 }
@@ -6,10 +6,21 @@ use std::{
 
 use crate::{
     error::{CompilerError, MemoryError},
-    repr::value::Value,
+    repr::{
+        fnc::{Call, NativeFnc},
+        value::Value,
+    },
 };
 
-#[derive(Debug, Clone, Copy)]
+/// Fixed-width big-endian encoding used for instruction operands
+pub trait AsBytes<const N: usize>: Sized {
+    type Error;
+
+    fn to_bytes(self) -> [u8; N];
+    fn try_from_bytes(bytes: [u8; N]) -> Result<Self, Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressMode {
     Narrow,
     Wide,
@@ -22,15 +33,33 @@ impl AddressMode {
             AddressMode::Wide => u16::MAX as usize,
         }
     }
+
+    pub const fn size(&self) -> usize {
+        match self {
+            AddressMode::Narrow => 1,
+            AddressMode::Wide => 2,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Address {
     Byte(u8),
     Word(u16),
 }
 
 impl Address {
+    /// Picks the narrowest address able to hold `addr`
+    pub fn new(addr: usize) -> Result<Self, CompilerError> {
+        if addr <= AddressMode::Narrow.max() {
+            Ok(Address::Byte(addr as u8))
+        } else if addr <= AddressMode::Wide.max() {
+            Ok(Address::Word(addr as u16))
+        } else {
+            Err(CompilerError::AddressOutOfRange(addr))
+        }
+    }
+
     pub const fn read(&self) -> usize {
         match self {
             Address::Byte(byte) => *byte as usize,
@@ -62,41 +91,128 @@ impl Display for Address {
 impl<const N: usize> AsBytes<N> for Address {
     type Error = CompilerError;
 
+    // Bytes beyond the requested width are truncated, so callers should pick `N` from `mode()`
     fn to_bytes(self) -> [u8; N] {
-        todo!()
+        let mut bytes = [0; N];
+        let word = (self.read() as u16).to_be_bytes();
+        let width = N.min(word.len());
+        bytes[N - width..].copy_from_slice(&word[word.len() - width..]);
+        bytes
     }
 
     fn try_from_bytes(bytes: [u8; N]) -> Result<Self, Self::Error> {
-        todo!()
+        match bytes[..] {
+            [byte] => Ok(Address::Byte(byte)),
+            [hi, lo] => Ok(Address::Word(u16::from_be_bytes([hi, lo]))),
+            _ => Err(CompilerError::InvalidAddressWidth(N)),
+        }
     }
 }
 
+/// A Number or String constant, which every instruction loading an equal value can share
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Constant {
+    Number(u64),
+    String(String),
+}
+
+/// Shared address space for the constant pool and global bindings of compiled programs
 #[derive(Debug, Default)]
-pub struct Memory<'m> {
+pub struct Memory {
     values: Vec<Option<Value>>,
-    names: HashMap<&'m str, usize>,
+    names: HashMap<String, usize>,
+    declared: HashMap<usize, bool>, // Address of each declared global, mapped to its mutability
+    constants: HashMap<Constant, usize>,
 }
 
-impl<'m> Memory<'m> {
+impl Memory {
+    pub fn new() -> Self {
+        let mut memory = Self::default();
+        for native in NativeFnc::ALL {
+            let addr = memory.reserve(&native.name());
+            memory.declared.insert(addr, false);
+            memory.values[addr] = Some(Value::from(native.clone()));
+        }
+        memory
+    }
+
+    /// Rebuilds memory from its raw parts, as read back from a compiled file
+    pub fn from_parts(
+        values: Vec<Option<Value>>,
+        names: Vec<(String, usize, Option<bool>)>,
+    ) -> Self {
+        let mut memory = Memory {
+            values,
+            ..Default::default()
+        };
+        for (name, addr, mutable) in names {
+            memory.names.insert(name, addr);
+            if let Some(mutable) = mutable {
+                memory.declared.insert(addr, mutable);
+            }
+        }
+        memory
+    }
+
+    /// Yields each global name with its address and, once declared, its mutability
+    pub fn names(&self) -> impl Iterator<Item = (&str, usize, Option<bool>)> {
+        self.names
+            .iter()
+            .map(|(name, addr)| (name.as_str(), *addr, self.declared.get(addr).copied()))
+    }
+
     pub fn declare_constant(&mut self, value: Value) -> usize {
+        let constant = match &value {
+            Value::Number(x) => Some(Constant::Number(x.to_bits())),
+            Value::String(string) => Some(Constant::String(string.to_string())),
+            _ => None,
+        };
+        if let Some(addr) = constant.as_ref().and_then(|c| self.constants.get(c)) {
+            return *addr;
+        }
+
         let addr = self.offset();
         self.values.push(Some(value));
+        if let Some(constant) = constant {
+            self.constants.insert(constant, addr);
+        }
         addr
     }
 
-    pub fn declare(&mut self, name: &'m str) -> Result<usize, MemoryError> {
-        if self.names.contains_key(name) {
-            panic!("variable already declared") // TODO: Create an error variant instead of panic
-        } else {
-            let addr = self.offset();
-            self.values.push(None);
-            self.names.insert(name, addr);
-            Ok(addr)
+    pub fn declare(&mut self, name: &str, mutable: bool) -> Result<usize, MemoryError> {
+        let mut addr = self.reserve(name);
+        match self.declared.get(&addr) {
+            Some(true) => Err(MemoryError::AlreadyDeclared(name.to_string()))?,
+            // Shadowing an immutable global binds the name to a fresh address
+            Some(false) => {
+                addr = self.offset();
+                self.values.push(None);
+                self.names.insert(name.to_string(), addr);
+            }
+            None => (),
         }
+
+        self.declared.insert(addr, mutable);
+        Ok(addr)
+    }
+
+    /// Returns the address bound to `name`, allocating one if the global has not been seen yet
+    pub fn reserve(&mut self, name: &str) -> usize {
+        if let Some(addr) = self.names.get(name) {
+            return *addr;
+        }
+
+        let addr = self.offset();
+        self.values.push(None);
+        self.names.insert(name.to_string(), addr);
+        addr
     }
 
     pub fn assign(&mut self, name: &str, value: Value) -> Result<(), MemoryError> {
         let addr = self.retrieve(name)?;
+        if !self.is_mutable(addr) && self.values[addr].is_some() {
+            Err(MemoryError::AssignToImmutable(name.to_string()))?
+        }
         self.values[addr] = Some(value);
 
         Ok(())
@@ -114,17 +230,34 @@ impl<'m> Memory<'m> {
     }
 
     pub fn retrieve(&self, name: &str) -> Result<usize, MemoryError> {
-        let addr = *self.names.get(name).unwrap();
+        let addr = *self
+            .names
+            .get(name)
+            .ok_or(MemoryError::NoSuchVariable(name.to_string()))?;
 
         Ok(addr)
     }
 
+    pub fn is_declared(&self, addr: usize) -> bool {
+        self.declared.contains_key(&addr)
+    }
+
+    pub fn is_mutable(&self, addr: usize) -> bool {
+        self.declared.get(&addr).copied().unwrap_or_default()
+    }
+
+    pub fn name_of(&self, addr: usize) -> Option<&str> {
+        self.names
+            .iter()
+            .find_map(|(name, a)| (*a == addr).then_some(name.as_str()))
+    }
+
     fn offset(&self) -> usize {
         self.values.len()
     }
 }
 
-impl<'m> Deref for Memory<'m> {
+impl Deref for Memory {
     type Target = Vec<Option<Value>>;
 
     fn deref(&self) -> &Self::Target {
@@ -132,7 +265,7 @@ impl<'m> Deref for Memory<'m> {
     }
 }
 
-impl<'m> DerefMut for Memory<'m> {
+impl DerefMut for Memory {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.values
     }
@@ -142,3 +275,16 @@ impl<'m> DerefMut for Memory<'m> {
 fn some_fn() {
     println!("{}", Address::Byte(255))
 }
+
+#[test]
+fn equal_constants_share_an_address() {
+    let mut memory = Memory::new();
+    let one = memory.declare_constant(Value::from(1.0));
+    let name = memory.declare_constant(Value::from("x"));
+    assert_eq!(memory.declare_constant(Value::from(1.0)), one);
+    assert_eq!(memory.declare_constant(Value::from("x")), name);
+    assert_ne!(
+        memory.declare_constant(Value::from(-0.0)),
+        memory.declare_constant(Value::from(0.0))
+    );
+}
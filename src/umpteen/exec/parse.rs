@@ -3,11 +3,15 @@ use crate::{
     error::ParseError,
     repr::{
         ast::{
-            expr::Expr,
+            expr::{Arm, Expr, Resolved, Segment, SubExpr},
             ops::{Binary, Unary},
-            stmt::Stmt,
+            pattern::Pattern,
+            stmt::{Field, Method, Param, Stmt, StmtKind, Variant},
         },
+        format::FormatSpec,
+        range::Range,
         token::{Token, TokenType},
+        types::Type,
         value::Value,
     },
     util::report_at,
@@ -96,20 +100,27 @@ impl<'p> Parser<'p> {
     }
 
     fn declaration(&mut self) -> Result<Stmt, ParseError> {
-        if catch!(self, Fnc) {
-            return self.declare_fnc();
-        }
-        if catch!(self, Var) {
-            return self.declare_variable(true);
-        }
-        if catch!(self, Let) {
-            return self.declare_variable(false);
-        }
+        let line = self.peek().line;
+
+        // `fnc` only declares a function when a name follows, otherwise it starts a lambda
+        let kind = if self.check_next(TokenType::Identifier) && catch!(self, Fnc) {
+            self.declare_fnc()?
+        } else if catch!(self, Var) {
+            self.declare_variable(true)?
+        } else if catch!(self, Let) {
+            self.declare_variable(false)?
+        } else if catch!(self, Type) {
+            self.declare_type()?
+        } else if catch!(self, Enum) {
+            self.declare_enum()?
+        } else {
+            self.statement()?
+        };
 
-        self.statement()
+        Ok(Stmt::new(kind, line))
     }
 
-    fn statement(&mut self) -> Result<Stmt, ParseError> {
+    fn statement(&mut self) -> Result<StmtKind, ParseError> {
         if catch!(self, If) {
             return self.conditional();
         }
@@ -118,42 +129,79 @@ impl<'p> Parser<'p> {
             return self.repetition();
         }
 
+        if catch!(self, While) {
+            return self.while_loop();
+        }
+
+        if catch!(self, For) {
+            return self.for_loop();
+        }
+
         if catch!(self, Break) {
             self.consume(TokenType::Semicolon)?;
-            return Ok(Stmt::Break);
+            return Ok(StmtKind::Break);
         }
 
         if catch!(self, Continue) {
             self.consume(TokenType::Semicolon)?;
-            return Ok(Stmt::Continue);
+            return Ok(StmtKind::Continue);
         }
 
         if catch!(self, Return) {
             if catch!(self, Semicolon) {
-                return Ok(Stmt::Return(Expr::Literal(Value::Empty)));
+                return Ok(StmtKind::Return(Expr::Literal(Value::Empty)));
             } else {
                 let expr = self.expression()?;
                 self.consume(TokenType::Semicolon)?;
-                return Ok(Stmt::Return(expr));
+                return Ok(StmtKind::Return(expr));
             }
         }
 
         if catch!(self, LeftBrace) {
-            return Ok(Stmt::Block(self.block()?));
+            return Ok(StmtKind::Block(self.block()?));
         }
 
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon)?;
-        Ok(Stmt::Expr(expr))
+        Ok(StmtKind::Expr(expr))
     }
 
-    fn repetition(&mut self) -> Result<Stmt, ParseError> {
+    fn repetition(&mut self) -> Result<StmtKind, ParseError> {
         self.consume(TokenType::LeftBrace)?;
         let block = self.block()?;
-        Ok(Stmt::Loop(block))
+        Ok(StmtKind::Loop(block))
+    }
+
+    fn while_loop(&mut self) -> Result<StmtKind, ParseError> {
+        let test = self.expression()?;
+        self.consume(TokenType::LeftBrace)?;
+        let body = self.block()?;
+
+        Ok(StmtKind::While { test, body })
+    }
+
+    fn for_loop(&mut self) -> Result<StmtKind, ParseError> {
+        let first = self.consume(TokenType::Identifier)?.lexeme();
+        let (index, item) = if catch!(self, Comma) {
+            (Some(first), self.consume(TokenType::Identifier)?.lexeme())
+        } else {
+            (None, first)
+        };
+        self.consume(TokenType::In)?;
+
+        let iterable = self.expression()?;
+        self.consume(TokenType::LeftBrace)?;
+        let body = self.block()?;
+
+        Ok(StmtKind::For {
+            index,
+            item,
+            iterable,
+            body,
+        })
     }
 
-    fn conditional(&mut self) -> Result<Stmt, ParseError> {
+    fn conditional(&mut self) -> Result<StmtKind, ParseError> {
         let expr = self.expression()?;
 
         self.consume(TokenType::LeftBrace)?;
@@ -164,7 +212,7 @@ impl<'p> Parser<'p> {
             None
         };
 
-        Ok(Stmt::Condition {
+        Ok(StmtKind::Condition {
             test: expr,
             then_branch,
             else_branch,
@@ -181,23 +229,54 @@ impl<'p> Parser<'p> {
         Ok(statements)
     }
 
-    fn declare_variable(&mut self, mutable: bool) -> Result<Stmt, ParseError> {
+    fn declare_variable(&mut self, mutable: bool) -> Result<StmtKind, ParseError> {
         let name = self.consume(TokenType::Identifier)?.lexeme();
 
         let init = if catch!(self, Equal) {
             Some(self.expression()?)
-        } else {
+        } else if mutable {
             None
+        } else {
+            // An immutable binding could never be given a value later
+            Err(ParseError::ExpectedToken(TokenType::Equal))?
         };
         self.consume(TokenType::Semicolon)?;
 
-        // TODO: Do something different for an immutable binding.
-        // For now, all bindings are mutable
-        Ok(Stmt::Declare { name, init })
+        Ok(StmtKind::Declare {
+            name,
+            init,
+            mutable,
+        })
     }
 
-    fn declare_fnc(&mut self) -> Result<Stmt, ParseError> {
+    fn declare_fnc(&mut self) -> Result<StmtKind, ParseError> {
         let name = self.consume(TokenType::Identifier)?.lexeme();
+        let (params, returns, body) = self.function(false, None)?;
+
+        Ok(StmtKind::Fnc {
+            name,
+            params,
+            returns,
+            body,
+        })
+    }
+
+    fn lambda(&mut self) -> Result<Expr, ParseError> {
+        let (params, returns, body) = self.function(true, None)?;
+        Ok(Expr::Lambda {
+            params,
+            returns,
+            body,
+        })
+    }
+
+    /// Parses the parameters, return type and body of a function. Lambdas may leave out any of
+    /// their types, and may give a single expression to return in place of a body
+    fn function(
+        &mut self,
+        lambda: bool,
+        receiver: Option<&str>,
+    ) -> Result<(Vec<Param>, Type, Ast), ParseError> {
         self.consume(TokenType::LeftParen)?;
 
         let mut first = true;
@@ -211,21 +290,149 @@ impl<'p> Parser<'p> {
                 self.consume(TokenType::Comma)?;
             }
 
-            let param = self.consume(TokenType::Identifier)?.lexeme();
-            self.consume(TokenType::Colon)?;
-            let param_type = self.consume(TokenType::TypeName)?.lexeme();
-            params.push((param.to_string(), param_type.to_string()));
+            let name = self.consume(TokenType::Identifier)?.lexeme();
+            let ty = if lambda && !self.check(TokenType::Colon) {
+                Type::Any
+            } else if let (Some(ty), true) = (receiver, params.is_empty() && name == "self") {
+                // A method's receiver is always an instance of its type
+                Type::Named(ty.to_string())
+            } else {
+                self.consume(TokenType::Colon)?;
+                self.type_annotation()?
+            };
+
+            // Optional parameters must all come after the required ones
+            let default = if catch!(self, Equal) {
+                Some(self.expression()?)
+            } else if params.iter().any(|p: &Param| p.default.is_some()) {
+                Err(ParseError::RequiredAfterOptional(name.clone()))?
+            } else {
+                None
+            };
+
+            params.push(Param { name, ty, default });
         }
 
-        if catch!(self, ThinArrow) {
-            let return_type = self.consume(TokenType::TypeName)?.lexeme();
-            println!("Return type: {}", return_type);
+        // Functions without a return annotation return Empty
+        let returns = if catch!(self, ThinArrow) {
+            self.type_annotation()?
+        } else if lambda {
+            Type::Any
+        } else {
+            Type::Empty
+        };
+
+        if lambda && catch!(self, FatArrow) {
+            let line = self.peek().line;
+            let expr = self.expression()?;
+            return Ok((
+                params,
+                returns,
+                vec![Stmt::new(StmtKind::Return(expr), line)],
+            ));
         }
 
         self.consume(TokenType::LeftBrace)?;
         let body = self.block()?;
 
-        Ok(Stmt::Fnc { name, params, body })
+        Ok((params, returns, body))
+    }
+
+    fn declare_type(&mut self) -> Result<StmtKind, ParseError> {
+        let name = self.type_name()?;
+        self.consume(TokenType::LeftBrace)?;
+
+        let mut fields: Vec<Field> = vec![];
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Fnc) {
+            let name = self.consume(TokenType::Identifier)?.lexeme();
+            if fields.iter().any(|f| f.name == name) {
+                Err(ParseError::DuplicateField(name.clone()))?
+            }
+            self.consume(TokenType::Colon)?;
+            let ty = self.type_annotation()?;
+            fields.push(Field { name, ty });
+            if !catch!(self, Comma) {
+                break;
+            }
+        }
+
+        // Methods follow the fields
+        let mut methods: Vec<Method> = vec![];
+        while catch!(self, Fnc) {
+            let method = self.consume(TokenType::Identifier)?.lexeme();
+            if fields.iter().any(|f| f.name == method) || methods.iter().any(|m| m.name == method) {
+                Err(ParseError::DuplicateField(method.clone()))?
+            }
+            let (params, returns, body) = self.function(false, Some(&name))?;
+            if params.first().is_none_or(|p| p.name != "self") {
+                Err(ParseError::MissingReceiver(method.clone()))?
+            }
+            methods.push(Method {
+                name: method,
+                params,
+                returns,
+                body,
+            });
+        }
+        self.consume(TokenType::RightBrace)?;
+
+        Ok(StmtKind::Type {
+            name,
+            fields,
+            methods,
+        })
+    }
+
+    fn declare_enum(&mut self) -> Result<StmtKind, ParseError> {
+        let name = self.type_name()?;
+        self.consume(TokenType::LeftBrace)?;
+
+        let mut variants: Vec<Variant> = vec![];
+        while !self.check(TokenType::RightBrace) {
+            let name = self.consume(TokenType::Identifier)?.lexeme();
+            if variants.iter().any(|v| v.name == name) {
+                Err(ParseError::DuplicateField(name.clone()))?
+            }
+
+            let mut fields = vec![];
+            if catch!(self, LeftParen) {
+                while !self.check(TokenType::RightParen) {
+                    fields.push(self.type_annotation()?);
+                    if !catch!(self, Comma) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightParen)?;
+            }
+            variants.push(Variant { name, fields });
+            if !catch!(self, Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace)?;
+
+        Ok(StmtKind::Enum { name, variants })
+    }
+
+    /// Reads the name of a declared type, which is capitalized so annotations can tell it apart
+    fn type_name(&mut self) -> Result<String, ParseError> {
+        let name = self.consume(TokenType::Identifier)?.lexeme();
+        if !name.starts_with(char::is_uppercase) {
+            Err(ParseError::LowercaseTypeName(name.clone()))?
+        }
+
+        Ok(name)
+    }
+
+    fn type_annotation(&mut self) -> Result<Type, ParseError> {
+        let name = self.consume(TokenType::Identifier)?.lexeme;
+        match Type::try_from(&*name) {
+            // Capitalized names other than the builtin types refer to declared records and enums
+            Err(ParseError::UnknownType(name)) if name.starts_with(char::is_uppercase) => {
+                Ok(Type::Named(name))
+            }
+            ty => ty,
+        }
     }
 
     fn expression(&mut self) -> Result<Expr, ParseError> {
@@ -247,46 +454,83 @@ impl<'p> Parser<'p> {
             return Ok(target);
         }
 
-        let op = self.previous();
+        let token = self.previous();
+        let op = match token.kind {
+            TokenType::Equal => None,
+            kind => Some(kind.try_into().unwrap()),
+        };
         let value = self.assignment()?;
 
-        if let Expr::Binding { name, index } = target.clone() {
-            let value = if op.kind == TokenType::Equal {
-                value
-            } else {
-                Expr::BinOp {
-                    left: boxed!(target),
-                    right: boxed!(value),
-                    op: op.kind.try_into().unwrap(),
-                }
-            };
-
-            Ok(Expr::Assign {
-                name,
+        // Places with operands of their own apply the operator themselves, so that the operands
+        // are only evaluated once
+        match target {
+            Expr::Binding { ref name, .. } => {
+                let value = match op {
+                    Some(op) => Expr::binary(target.clone(), value, op),
+                    None => value,
+                };
+                Ok(Expr::Assign {
+                    name: name.clone(),
+                    expr: boxed!(value),
+                    resolved: Resolved::Global(0),
+                })
+            }
+            Expr::Get { object, field } => Ok(Expr::Set {
+                object,
+                field,
+                op,
+                expr: boxed!(value),
+            }),
+            Expr::Index { object, index } => Ok(Expr::SetIndex {
+                object,
                 index,
+                op,
                 expr: boxed!(value),
-            })
-        } else {
-            Err(ParseError::InvalidAssignmentTarget(op.lexeme.to_owned()))
+            }),
+            _ => Err(ParseError::InvalidAssignmentTarget(token.lexeme())),
         }
     }
 
     fn equality(&mut self) -> Result<Expr, ParseError> {
         binop!(self, comparison,
             BangEqual => Inequality,
-            EqualEqual => Equality
+            EqualEqual => Equality,
+            Is => Identity
         )
     }
 
     fn comparison(&mut self) -> Result<Expr, ParseError> {
-        binop!(self, term,
+        binop!(self, range,
             Greater => GreaterThan,
             GreaterEqual => GreaterOrEqual,
             Less => LessThan,
-            LessEqual => LessOrEqual
+            LessEqual => LessOrEqual,
+            In => Membership
         )
     }
 
+    fn range(&mut self) -> Result<Expr, ParseError> {
+        let start = self.term()?;
+        if !catch!(self, DotDot, DotDotEqual) {
+            return Ok(start);
+        }
+
+        let inclusive = self.previous().kind == TokenType::DotDotEqual;
+        let end = self.term()?;
+        let step = if catch!(self, By) {
+            Some(boxed!(self.term()?))
+        } else {
+            None
+        };
+
+        Ok(Expr::Range {
+            start: boxed!(start),
+            end: boxed!(end),
+            step,
+            inclusive,
+        })
+    }
+
     fn term(&mut self) -> Result<Expr, ParseError> {
         binop!(self, factor,
             Plus => Add,
@@ -323,6 +567,14 @@ impl<'p> Parser<'p> {
         loop {
             if catch!(self, LeftParen) {
                 expr = self.finish_call(expr)?;
+            } else if catch!(self, Dot) {
+                let field = self.consume(TokenType::Identifier)?.lexeme();
+                expr = Expr::Get {
+                    object: boxed!(expr),
+                    field,
+                };
+            } else if catch!(self, LeftBracket) {
+                expr = self.finish_index(expr)?;
             } else {
                 break;
             }
@@ -331,6 +583,171 @@ impl<'p> Parser<'p> {
         Ok(expr)
     }
 
+    /// Parses `[index]` or a slice `[start:end:step]`, where each part of a slice is optional
+    fn finish_index(&mut self, object: Expr) -> Result<Expr, ParseError> {
+        let object = boxed!(object);
+        let start = self.slice_part()?;
+        if !catch!(self, Colon) {
+            let index = start.ok_or(ParseError::ExpectedExpression)?;
+            self.consume(TokenType::RightBracket)?;
+            return Ok(Expr::Index { object, index });
+        }
+
+        let end = self.slice_part()?;
+        let step = match catch!(self, Colon) {
+            true => self.slice_part()?,
+            false => None,
+        };
+        self.consume(TokenType::RightBracket)?;
+
+        Ok(Expr::Slice {
+            object,
+            start,
+            end,
+            step,
+        })
+    }
+
+    fn slice_part(&mut self) -> Result<Option<SubExpr>, ParseError> {
+        if self.check(TokenType::Colon) || self.check(TokenType::RightBracket) {
+            return Ok(None);
+        }
+
+        Ok(Some(boxed!(self.expression()?)))
+    }
+
+    fn finish_interpolation(&mut self) -> Result<Expr, ParseError> {
+        let mut segments = vec![];
+        loop {
+            let text = self.previous().lexeme.to_string();
+            if !text.is_empty() {
+                segments.push(Segment::Text(text));
+            }
+            if self.previous().kind == TokenType::StringEnd {
+                break;
+            }
+
+            let expr = self.expression()?;
+            let spec = match catch!(self, FormatSpec) {
+                true => FormatSpec::parse(&self.previous().lexeme)?,
+                false => FormatSpec::default(),
+            };
+            segments.push(Segment::Value(expr, spec));
+
+            if !catch!(self, StringMiddle, StringEnd) {
+                Err(ParseError::ExpectedToken(TokenType::StringEnd))?
+            }
+        }
+
+        Ok(Expr::Interpolation(segments))
+    }
+
+    fn finish_match(&mut self) -> Result<Expr, ParseError> {
+        let subject = boxed!(self.expression()?);
+        self.consume(TokenType::LeftBrace)?;
+
+        let mut arms = vec![];
+        while !self.check(TokenType::RightBrace) {
+            let pattern = self.pattern()?;
+            let mut bindings = pattern.bindings();
+            bindings.sort();
+            if let Some(twice) = bindings.windows(2).find(|pair| pair[0] == pair[1]) {
+                Err(ParseError::DuplicateBinding(twice[0].to_string()))?
+            }
+
+            self.consume(TokenType::FatArrow)?;
+            let body = self.expression()?;
+            arms.push(Arm { pattern, body });
+            if !catch!(self, Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace)?;
+
+        Ok(Expr::Match { subject, arms })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        if catch!(self, LeftBracket) {
+            let mut items = vec![];
+            let mut rest = None;
+            while !self.check(TokenType::RightBracket) {
+                // `..` takes whatever is left, so it has to come last
+                if catch!(self, DotDot) {
+                    rest = Some(boxed!(match catch!(self, Identifier) {
+                        true => Pattern::Binding(self.previous().lexeme()),
+                        false => Pattern::Wildcard,
+                    }));
+                    break;
+                }
+                items.push(self.pattern()?);
+                if !catch!(self, Comma) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBracket)?;
+            return Ok(Pattern::List { items, rest });
+        }
+
+        if catch!(self, Identifier) {
+            let name = self.previous().lexeme();
+            if !catch!(self, Dot) {
+                return Ok(match name.as_str() {
+                    "_" => Pattern::Wildcard,
+                    _ => Pattern::Binding(name),
+                });
+            }
+
+            let variant = self.consume(TokenType::Identifier)?.lexeme();
+            let mut fields = vec![];
+            if catch!(self, LeftParen) {
+                while !self.check(TokenType::RightParen) {
+                    fields.push(self.pattern()?);
+                    if !catch!(self, Comma) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightParen)?;
+            }
+            return Ok(Pattern::Variant {
+                ty: name,
+                variant,
+                fields,
+            });
+        }
+
+        let start = self.pattern_literal()?;
+        if !catch!(self, DotDot, DotDotEqual) {
+            return Ok(Pattern::Literal(start));
+        }
+
+        let inclusive = self.previous().kind == TokenType::DotDotEqual;
+        let end = self.pattern_literal()?;
+        let step = match catch!(self, By) {
+            true => self.pattern_literal()?,
+            false => Value::Empty,
+        };
+        let range = Range::new(&start, &end, &step, inclusive)
+            .map_err(|e| ParseError::InvalidPattern(e.to_string()))?;
+        Ok(Pattern::Range(range))
+    }
+
+    fn pattern_literal(&mut self) -> Result<Value, ParseError> {
+        let negative = catch!(self, Minus);
+        if !catch!(self, Empty, True, False, Number, String) {
+            Err(ParseError::UnexpectedToken(self.peek().kind))?
+        }
+        let Expr::Literal(value) = self.literal()? else {
+            unreachable!()
+        };
+
+        match (negative, value) {
+            (true, Value::Number(x)) => Ok(Value::Number(-x)),
+            (true, value) => Err(ParseError::InvalidPattern(format!("-{}", value))),
+            (false, value) => Ok(value),
+        }
+    }
+
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
         let mut args = vec![];
 
@@ -355,25 +772,26 @@ impl<'p> Parser<'p> {
     fn primary(&mut self) -> Result<Expr, ParseError> {
         if catch!(self, Identifier) {
             let name = self.previous().lexeme();
-            if catch!(self, LeftBracket) {
-                let index = Some(boxed!(self.expression()?));
-                self.consume(TokenType::RightBracket)?;
-                return Ok(Expr::Binding { name, index });
-            }
-            return Ok(Expr::Binding { name, index: None });
+            return Ok(Expr::Binding {
+                name,
+                resolved: Resolved::Global(0),
+            });
         }
 
         if catch!(self, Empty, True, False, Number, String) {
-            let tk = self.previous();
-            let expr = literal!(self,
-                True => Boolean(true),
-                False => Boolean(false),
-                Empty => Empty,
-                Number => Number(tk.lexeme.parse()?),
-                String => String(boxed!(tk.lexeme.to_owned()))
-            );
+            return self.literal();
+        }
+
+        if catch!(self, StringStart) {
+            return self.finish_interpolation();
+        }
 
-            return Ok(expr);
+        if catch!(self, Match) {
+            return self.finish_match();
+        }
+
+        if catch!(self, Fnc) {
+            return self.lambda();
         }
 
         if catch!(self, LeftParen) {
@@ -396,17 +814,45 @@ impl<'p> Parser<'p> {
             return Ok(Expr::List(list));
         }
 
+        if catch!(self, LeftBrace) {
+            let mut entries = vec![];
+
+            while !self.check(TokenType::RightBrace) {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon)?;
+                entries.push((key, self.expression()?));
+                if !catch!(self, Comma) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace)?;
+            return Ok(Expr::Map(entries));
+        }
+
         Err(ParseError::UnexpectedToken(self.peek().kind))
     }
 
-    fn advance(&mut self) -> Token {
+    fn literal(&mut self) -> Result<Expr, ParseError> {
+        let tk = self.previous();
+        let expr = literal!(self,
+            True => Boolean(true),
+            False => Boolean(false),
+            Empty => Empty,
+            Number => Number(tk.lexeme.parse()?),
+            String => String(boxed!(tk.lexeme.to_string()))
+        );
+
+        Ok(expr)
+    }
+
+    fn advance(&mut self) -> Token<'p> {
         if !self.at_end() {
             self.index += 1;
         }
         self.previous()
     }
 
-    fn consume(&mut self, kind: TokenType) -> Result<Token, ParseError> {
+    fn consume(&mut self, kind: TokenType) -> Result<Token<'p>, ParseError> {
         if self.check(kind) {
             Ok(self.advance())
         } else {
@@ -415,7 +861,7 @@ impl<'p> Parser<'p> {
     }
 
     fn previous(&self) -> Token<'p> {
-        self.tokens[self.index.saturating_sub(1)]
+        self.tokens[self.index.saturating_sub(1)].clone()
     }
 
     fn check(&self, kind: TokenType) -> bool {
@@ -426,11 +872,17 @@ impl<'p> Parser<'p> {
         }
     }
 
+    fn check_next(&self, kind: TokenType) -> bool {
+        self.tokens
+            .get(self.index + 1)
+            .is_some_and(|tk| tk.kind == kind)
+    }
+
     fn at_end(&self) -> bool {
         self.peek().kind == TokenType::Eof
     }
 
-    fn peek(&self) -> Token {
-        self.tokens[self.index]
+    fn peek(&self) -> Token<'p> {
+        self.tokens[self.index].clone()
     }
 }
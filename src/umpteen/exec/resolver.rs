@@ -0,0 +1,516 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    boxed,
+    error::{Line, ResolveError, UmpteenError},
+    repr::{
+        ast::{
+            expr::{Arm, Expr, Local, Resolved, Segment},
+            pattern::Pattern,
+            stmt::{Param, Stmt, StmtKind, Variant},
+        },
+        types::Type,
+        value::Value,
+    },
+    util::warn_line,
+};
+
+use super::{interpreter::Divergence, parse::Ast};
+
+struct Declared {
+    name: String,
+    line: Line,
+    used: bool,
+}
+
+/// Static pass between parsing and execution that binds every local to a (depth, slot) pair
+/// and rejects programs that could only fail once they are run
+pub struct Resolver<'r> {
+    globals: &'r mut HashMap<String, usize>, // How many times each global has been declared
+    enums: &'r mut HashMap<String, Vec<Variant>>, // Checked against the patterns that name them
+    types: &'r mut HashSet<String>,          // Records and enums, which annotations may name
+    annotations: Vec<(String, Line)>, // Named in annotations, checked once every type is known
+    scopes: Vec<Vec<Declared>>,
+    unresolved: Vec<(String, usize)>, // Names left to the globals, with the scopes around them
+    functions: usize,
+    loops: usize,
+    line: Line,
+}
+
+impl<'r> Resolver<'r> {
+    pub fn new(
+        globals: &'r mut HashMap<String, usize>,
+        enums: &'r mut HashMap<String, Vec<Variant>>,
+        types: &'r mut HashSet<String>,
+    ) -> Self {
+        Resolver {
+            globals,
+            enums,
+            types,
+            annotations: vec![],
+            scopes: vec![],
+            unresolved: vec![],
+            functions: 0,
+            loops: 0,
+            line: Line::new(0),
+        }
+    }
+
+    pub fn resolve(mut self, ast: &mut Ast) -> Result<(), UmpteenError> {
+        for stmt in ast {
+            self.statement(stmt)
+                .map_err(|e| UmpteenError::Located(boxed!(e.into()), self.line))?;
+        }
+
+        // Types may be named before they are declared, as long as the program declares them
+        for (name, line) in self.annotations {
+            if !self.types.contains(&name) {
+                let err = ResolveError::UnknownType(name);
+                Err(UmpteenError::Located(boxed!(err.into()), line))?
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &mut Stmt) -> Result<(), ResolveError> {
+        self.line = stmt.line;
+
+        match &mut stmt.kind {
+            StmtKind::Expr(expr) => self.expression(expr)?,
+            StmtKind::Declare { name, init, .. } => {
+                // The initializer is resolved first so it still sees any shadowed binding
+                if let Some(expr) = init {
+                    self.expression(expr)?;
+                }
+                self.declare(name, false)?;
+            }
+            StmtKind::Block(statements) => self.block(statements)?,
+            StmtKind::Condition {
+                test,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(test)?;
+                self.block(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch)?;
+                }
+            }
+            StmtKind::Loop(body) => {
+                self.loops += 1;
+                self.block(body)?;
+                self.loops -= 1;
+            }
+            StmtKind::While { test, body } => {
+                self.expression(test)?;
+                self.loops += 1;
+                self.block(body)?;
+                self.loops -= 1;
+            }
+            StmtKind::For {
+                index,
+                item,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable)?;
+                self.loops += 1;
+                // The loop variables live in the same scope as the body
+                self.scopes.push(vec![]);
+                if let Some(index) = index {
+                    self.declare(index, false)?;
+                }
+                self.declare(item, false)?;
+                for stmt in body {
+                    self.statement(stmt)?;
+                }
+                self.end_scope();
+                self.loops -= 1;
+            }
+            StmtKind::Break if self.loops == 0 => Err(Divergence::Break)?,
+            StmtKind::Continue if self.loops == 0 => Err(Divergence::Continue)?,
+            StmtKind::Break | StmtKind::Continue | StmtKind::Exit => (),
+            StmtKind::Return(expr) => {
+                if self.functions == 0 {
+                    Err(ResolveError::IllegalDivergence(
+                        Divergence::Return(Value::Empty).to_string(),
+                    ))?
+                }
+                self.expression(expr)?;
+            }
+            StmtKind::Fnc {
+                name,
+                params,
+                returns,
+                body,
+            } => {
+                // Bind the name before the body so the function can call itself
+                self.declare(name, true)?;
+                self.annotation(returns);
+                self.function(params, body)?;
+            }
+            StmtKind::Type {
+                name,
+                fields,
+                methods,
+            } => {
+                self.types.insert(name.clone());
+                self.declare(name, true)?;
+                for field in fields.iter() {
+                    self.annotation(&field.ty);
+                }
+                for method in methods {
+                    self.annotation(&method.returns);
+                    self.function(&mut method.params, &mut method.body)?;
+                }
+            }
+            StmtKind::Enum { name, variants } => {
+                self.types.insert(name.clone());
+                self.enums.insert(name.clone(), variants.clone());
+                self.declare(name, true)?;
+                for ty in variants.iter().flat_map(|v| &v.fields) {
+                    self.annotation(ty);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn function(&mut self, params: &mut [Param], body: &mut Ast) -> Result<(), ResolveError> {
+        let loops = std::mem::take(&mut self.loops);
+        self.functions += 1;
+        self.scopes.push(vec![]);
+        for param in params.iter_mut() {
+            if let Some(default) = &mut param.default {
+                self.expression(default)?;
+            }
+            self.annotation(&param.ty);
+            self.declare(&param.name, true)?;
+        }
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        self.end_scope();
+        self.functions -= 1;
+        self.loops = loops;
+
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &mut Ast) -> Result<(), ResolveError> {
+        self.scopes.push(vec![]);
+        for stmt in statements {
+            self.statement(stmt)?;
+        }
+        self.end_scope();
+
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &mut Expr) -> Result<(), ResolveError> {
+        match expr {
+            Expr::Literal(_) => (),
+            Expr::Interpolation(segments) => {
+                for segment in segments {
+                    if let Segment::Value(expr, _) = segment {
+                        self.expression(expr)?;
+                    }
+                }
+            }
+            Expr::List(expressions) => {
+                for expr in expressions {
+                    self.expression(expr)?;
+                }
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+            }
+            Expr::Binding { name, resolved } => *resolved = self.lookup(name)?,
+            Expr::Assign {
+                name,
+                expr,
+                resolved,
+            } => {
+                self.expression(expr)?;
+                *resolved = self.lookup(name)?;
+            }
+            Expr::Grouping { expr } | Expr::UnOp { expr, .. } => self.expression(expr)?,
+            Expr::BinOp { left, right, .. } => {
+                self.expression(left)?;
+                self.expression(right)?;
+            }
+            Expr::Call { callee, args } => {
+                self.expression(callee)?;
+                for arg in args {
+                    self.expression(arg)?;
+                }
+            }
+            Expr::Lambda {
+                params,
+                returns,
+                body,
+            } => {
+                self.annotation(returns);
+                self.function(params, body)?;
+            }
+            Expr::Match { subject, arms } => {
+                self.expression(subject)?;
+                for arm in arms.iter_mut() {
+                    self.pattern(&arm.pattern)?;
+                    self.scopes.push(vec![]);
+                    for name in arm.pattern.bindings() {
+                        self.declare(name, false)?;
+                    }
+                    self.expression(&mut arm.body)?;
+                    self.end_scope();
+                }
+                self.exhaustive(arms)?;
+            }
+            Expr::Get { object, .. } => self.expression(object)?,
+            Expr::Set { object, expr, .. } => {
+                self.expression(object)?;
+                self.expression(expr)?;
+            }
+            Expr::Index { object, index } => {
+                self.expression(object)?;
+                self.expression(index)?;
+            }
+            Expr::Slice {
+                object,
+                start,
+                end,
+                step,
+            } => {
+                self.expression(object)?;
+                for part in [start, end, step].into_iter().flatten() {
+                    self.expression(part)?;
+                }
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                expr,
+                ..
+            } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.expression(expr)?;
+            }
+            Expr::Range {
+                start, end, step, ..
+            } => {
+                self.expression(start)?;
+                self.expression(end)?;
+                if let Some(step) = step {
+                    self.expression(step)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn annotation(&mut self, ty: &Type) {
+        if let Type::Named(name) = ty {
+            self.annotations.push((name.clone(), self.line));
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> Result<(), ResolveError> {
+        match pattern {
+            Pattern::List { items, .. } => {
+                for item in items {
+                    self.pattern(item)?;
+                }
+            }
+            Pattern::Variant {
+                ty,
+                variant,
+                fields,
+            } => {
+                let name = format!("{}.{}", ty, variant);
+                let expected = self
+                    .enums
+                    .get(ty)
+                    .and_then(|variants| variants.iter().find(|v| v.name == *variant))
+                    .ok_or_else(|| ResolveError::NoSuchVariant(name.clone()))?
+                    .fields
+                    .len();
+                if fields.len() != expected {
+                    Err(ResolveError::VariantArity {
+                        variant: name,
+                        expected,
+                        got: fields.len(),
+                    })?
+                }
+
+                self.lookup(ty)?;
+                for field in fields {
+                    self.pattern(field)?;
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Rejects a match over an enum or Boolean, the only types whose values can all be listed,
+    /// that leaves some of them out. Any other match fails at runtime if nothing accepts a value
+    fn exhaustive(&self, arms: &[Arm]) -> Result<(), ResolveError> {
+        if arms.iter().any(|arm| arm.pattern.irrefutable()) {
+            return Ok(());
+        }
+
+        let covered = |pattern: &Pattern| arms.iter().any(|arm| arm.pattern == *pattern);
+        let mut missing = vec![];
+        match arms.first().map(|arm| &arm.pattern) {
+            Some(Pattern::Variant { ty, .. }) => {
+                for variant in &self.enums[ty] {
+                    // A variant is only covered by an arm that accepts any payload
+                    let covered = arms.iter().any(|arm| match &arm.pattern {
+                        Pattern::Variant {
+                            ty: t,
+                            variant: v,
+                            fields,
+                        } => {
+                            t == ty && *v == variant.name && fields.iter().all(Pattern::irrefutable)
+                        }
+                        _ => false,
+                    });
+                    if !covered {
+                        missing.push(format!("{}.{}", ty, variant.name));
+                    }
+                }
+            }
+            Some(Pattern::Literal(Value::Boolean(_))) => {
+                for x in [true, false] {
+                    if !covered(&Pattern::Literal(Value::Boolean(x))) {
+                        missing.push(x.to_string());
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(ResolveError::NonExhaustiveMatch(missing)),
+        }
+    }
+
+    /// Finds the innermost local called `name`. Anything else is the latest global of that name,
+    /// which must already be declared unless it is only referenced from inside a function body
+    fn lookup(&mut self, name: &str) -> Result<Resolved, ResolveError> {
+        for (depth, scope) in self.scopes.iter_mut().rev().enumerate() {
+            if let Some(slot) = scope.iter().rposition(|d| d.name == name) {
+                scope[slot].used = true;
+                return Ok(Resolved::Local(Local { depth, slot }));
+            }
+        }
+
+        let declared = self.globals.get(name).copied().unwrap_or_default();
+        if declared == 0 {
+            if self.functions == 0 {
+                Err(ResolveError::UseBeforeDeclaration(name.to_string()))?
+            }
+            self.unresolved.push((name.to_string(), self.scopes.len()));
+        }
+
+        Ok(Resolved::Global(declared.saturating_sub(1)))
+    }
+
+    /// Declares `name` in the innermost scope, rejecting it if a function body enclosed by that
+    /// scope already referred to the name, as the reference was taken to be a global
+    fn declare(&mut self, name: &str, used: bool) -> Result<(), ResolveError> {
+        let depth = self.scopes.len();
+        let Some(scope) = self.scopes.last_mut() else {
+            *self.globals.entry(name.to_string()).or_default() += 1;
+            return Ok(());
+        };
+
+        if self
+            .unresolved
+            .iter()
+            .any(|(n, scopes)| n == name && *scopes >= depth)
+        {
+            Err(ResolveError::UseBeforeDeclaration(name.to_string()))?
+        }
+        scope.push(Declared {
+            name: name.to_string(),
+            line: self.line,
+            used,
+        });
+
+        Ok(())
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        let depth = self.scopes.len();
+        self.unresolved.retain_mut(|(_, scopes)| {
+            *scopes = depth.min(*scopes);
+            *scopes > 0
+        });
+        for declared in scope {
+            if !declared.used && !declared.name.starts_with('_') {
+                warn_line(
+                    format!("unused variable `{}`", declared.name),
+                    declared.line,
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn resolves_locals_and_rejects_scope_errors() {
+    use super::{lexer::Lexer, parse::Parser};
+
+    let resolve = |src: &str| {
+        let mut ast = Parser::new(Lexer::new(src).scan().unwrap()).parse();
+        let mut globals = HashMap::from([(String::from("print"), 1)]);
+        Resolver::new(&mut globals, &mut HashMap::new(), &mut HashSet::new())
+            .resolve(&mut ast)
+            .map(|_| ast)
+    };
+
+    let ast = resolve("fnc f(a: Number) { var b = a; { print(b); } }").unwrap();
+    let StmtKind::Fnc { body, .. } = &ast[0].kind else {
+        panic!("expected a function")
+    };
+    let StmtKind::Block(block) = &body[1].kind else {
+        panic!("expected a block")
+    };
+    let StmtKind::Expr(Expr::Call { args, .. }) = &block[0].kind else {
+        panic!("expected a call")
+    };
+    assert_eq!(
+        args[0],
+        Expr::Binding {
+            name: String::from("b"),
+            resolved: Resolved::Local(Local { depth: 1, slot: 1 }),
+        }
+    );
+
+    for src in [
+        "print(x); var x = 1;",
+        "break;",
+        "{ continue; }",
+        "return 0;",
+        "{ fnc f() -> Number { return g(); } fnc g() -> Number { return 1; } f(); }",
+        "fnc f(p: Pointe) {}",
+        "enum Shape { Circle(Nmber) }",
+    ] {
+        assert!(resolve(src).is_err(), "`{}` should not resolve", src);
+    }
+    assert!(resolve("fnc f() { loop { break; } return g(); }").is_ok());
+    assert!(
+        resolve("fnc f(n: Node) -> Tree {} type Node { next: Node } enum Tree { Leaf }").is_ok()
+    );
+}
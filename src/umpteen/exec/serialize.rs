@@ -0,0 +1,690 @@
+//! Reading and writing of compiled programs in the `.umc` format
+//!
+//! A file starts with the `MAGIC` bytes and a big-endian `VERSION`, followed by the memory image
+//! (constant pool and global slots), the global name table, and finally the top-level script.
+//! Function prototypes live in the constant pool and carry their own code and line table.
+
+use std::collections::HashSet;
+
+use crate::{
+    error::{FormatError, Line},
+    repr::{
+        ast::{
+            pattern::Pattern,
+            stmt::{Field, Variant},
+        },
+        chunk::{Chunk, OpCode, Operand, Prototype},
+        enumeration::EnumType,
+        fnc::{Call, Closure, Fnc, NativeFnc},
+        format::FormatSpec,
+        object::Object,
+        range::Range,
+        record::RecordType,
+        types::Type,
+        value::Value,
+    },
+};
+
+use super::memory::{AddressMode, Memory};
+
+pub const MAGIC: &[u8; 4] = b"UMC\0";
+pub const VERSION: u16 = 15;
+
+const TAG_UNSET: u8 = 0;
+const TAG_EMPTY: u8 = 1;
+const TAG_BOOLEAN: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_NATIVE: u8 = 5;
+const TAG_FNC: u8 = 6;
+const TAG_RECORD: u8 = 7;
+const TAG_ENUM: u8 = 8;
+
+const TYPE_NONE: u8 = 0;
+const TYPE_EMPTY: u8 = 1;
+const TYPE_BOOLEAN: u8 = 2;
+const TYPE_NUMBER: u8 = 3;
+const TYPE_STRING: u8 = 4;
+const TYPE_LIST: u8 = 5;
+const TYPE_FNC: u8 = 6;
+const TYPE_RANGE: u8 = 7;
+const TYPE_MAP: u8 = 8;
+const TYPE_ENUM: u8 = 9;
+const TYPE_NAMED: u8 = 10;
+const TYPE_ANY: u8 = 11;
+
+const PATTERN_WILDCARD: u8 = 0;
+const PATTERN_BINDING: u8 = 1;
+const PATTERN_LITERAL: u8 = 2;
+const PATTERN_RANGE: u8 = 3;
+const PATTERN_LIST: u8 = 4;
+const PATTERN_VARIANT: u8 = 5;
+
+const UNDECLARED: u8 = 0;
+const MUTABLE: u8 = 1;
+const IMMUTABLE: u8 = 2;
+
+pub fn save(memory: &Memory, script: &Closure) -> Result<Vec<u8>, FormatError> {
+    let mut out = Writer::default();
+    out.bytes(MAGIC);
+    out.u16(VERSION);
+
+    out.u32(memory.len());
+    for value in memory.iter() {
+        out.value(value.as_ref())?;
+    }
+
+    let names: Vec<_> = memory.names().collect();
+    out.u32(names.len());
+    for (name, addr, mutable) in names {
+        out.string(name);
+        out.u32(addr);
+        out.u8(match mutable {
+            None => UNDECLARED,
+            Some(true) => MUTABLE,
+            Some(false) => IMMUTABLE,
+        });
+    }
+
+    out.prototype(script.proto())?;
+
+    Ok(out.0)
+}
+
+pub fn load(bytes: &[u8]) -> Result<(Memory, Closure), FormatError> {
+    let mut input = Reader { bytes, pos: 0 };
+    if input.take(MAGIC.len())? != MAGIC {
+        Err(FormatError::BadMagic)?
+    }
+
+    let version = input.u16()?;
+    if version != VERSION {
+        Err(FormatError::UnsupportedVersion(version))?
+    }
+
+    let mut values = vec![];
+    for _ in 0..input.u32()? {
+        values.push(input.value()?);
+    }
+
+    let mut names = vec![];
+    for _ in 0..input.u32()? {
+        let name = input.string()?;
+        let addr = input.u32()?;
+        if addr >= values.len() {
+            Err(FormatError::InvalidBytecode(format!(
+                "global `{}` points outside of memory",
+                name
+            )))?
+        }
+        let mutable = match input.u8()? {
+            UNDECLARED => None,
+            MUTABLE => Some(true),
+            IMMUTABLE => Some(false),
+            _ => Err(FormatError::InvalidBytecode(format!(
+                "global `{}` has an invalid binding",
+                name
+            )))?,
+        };
+        names.push((name, addr, mutable));
+    }
+
+    let script = input.prototype()?;
+    if input.pos != bytes.len() {
+        Err(FormatError::InvalidBytecode(String::from(
+            "trailing data after script",
+        )))?
+    }
+
+    let memory = Memory::from_parts(values, names);
+    verify(&memory, &script, &mut vec![], &mut HashSet::new())?;
+
+    Ok((memory, Closure::new(script)))
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.bytes(&x.to_be_bytes());
+    }
+
+    fn u32(&mut self, x: usize) {
+        self.bytes(&(x as u32).to_be_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len());
+        self.bytes(s.as_bytes());
+    }
+
+    fn value(&mut self, value: Option<&Value>) -> Result<(), FormatError> {
+        match value {
+            None => self.u8(TAG_UNSET),
+            Some(Value::Empty) => self.u8(TAG_EMPTY),
+            Some(Value::Boolean(x)) => {
+                self.u8(TAG_BOOLEAN);
+                self.u8(*x as u8);
+            }
+            Some(Value::Number(x)) => {
+                self.u8(TAG_NUMBER);
+                self.bytes(&x.to_be_bytes());
+            }
+            Some(Value::String(s)) => {
+                self.u8(TAG_STRING);
+                self.string(s);
+            }
+            Some(value @ Value::Object(obj)) => match *obj.borrow() {
+                Object::Fnc(Fnc::Native(ref native)) => {
+                    self.u8(TAG_NATIVE);
+                    self.string(&native.name());
+                }
+                Object::Fnc(Fnc::Compiled(ref closure)) => {
+                    self.u8(TAG_FNC);
+                    self.prototype(closure.proto())?;
+                }
+                Object::Fnc(Fnc::Record(ref ty)) => {
+                    self.u8(TAG_RECORD);
+                    self.string(&ty.name);
+                    self.u32(ty.fields.len());
+                    for field in &ty.fields {
+                        self.string(&field.name);
+                        self.ty(Some(&field.ty));
+                    }
+                }
+                Object::Enum(ref ty) => {
+                    self.u8(TAG_ENUM);
+                    self.string(&ty.name);
+                    self.u32(ty.variants.len());
+                    for variant in &ty.variants {
+                        self.string(&variant.name);
+                        self.u32(variant.fields.len());
+                        for ty in &variant.fields {
+                            self.ty(Some(ty));
+                        }
+                    }
+                }
+                _ => Err(FormatError::Unserializable(value.to_string()))?,
+            },
+        }
+
+        Ok(())
+    }
+
+    fn ty(&mut self, ty: Option<&Type>) {
+        self.u8(match ty {
+            None => TYPE_NONE,
+            Some(Type::Empty) => TYPE_EMPTY,
+            Some(Type::Boolean) => TYPE_BOOLEAN,
+            Some(Type::Number) => TYPE_NUMBER,
+            Some(Type::String) => TYPE_STRING,
+            Some(Type::List) => TYPE_LIST,
+            Some(Type::Fnc) => TYPE_FNC,
+            Some(Type::Range) => TYPE_RANGE,
+            Some(Type::Map) => TYPE_MAP,
+            Some(Type::Enum) => TYPE_ENUM,
+            Some(Type::Any) => TYPE_ANY,
+            Some(Type::Named(_)) => TYPE_NAMED,
+        });
+        // Record types are known by name, since their declaration lives in a constant
+        if let Some(Type::Named(name)) = ty {
+            self.string(name);
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> Result<(), FormatError> {
+        match pattern {
+            Pattern::Wildcard => self.u8(PATTERN_WILDCARD),
+            Pattern::Binding(name) => {
+                self.u8(PATTERN_BINDING);
+                self.string(name);
+            }
+            Pattern::Literal(value) => {
+                self.u8(PATTERN_LITERAL);
+                self.value(Some(value))?;
+            }
+            Pattern::Range(range) => {
+                let (start, end, step, inclusive) = range.parts();
+                self.u8(PATTERN_RANGE);
+                for x in [start, end, step] {
+                    self.bytes(&x.to_be_bytes());
+                }
+                self.u8(inclusive as u8);
+            }
+            Pattern::List { items, rest } => {
+                self.u8(PATTERN_LIST);
+                self.u32(items.len());
+                for item in items {
+                    self.pattern(item)?;
+                }
+                self.u8(rest.is_some() as u8);
+                if let Some(rest) = rest {
+                    self.pattern(rest)?;
+                }
+            }
+            Pattern::Variant {
+                ty,
+                variant,
+                fields,
+            } => {
+                self.u8(PATTERN_VARIANT);
+                self.string(ty);
+                self.string(variant);
+                self.u32(fields.len());
+                for field in fields {
+                    self.pattern(field)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn prototype(&mut self, proto: &Prototype) -> Result<(), FormatError> {
+        self.string(&proto.name);
+        self.u32(proto.params.len());
+        for ty in &proto.params {
+            self.ty(Some(ty));
+        }
+        self.u32(proto.required);
+        self.ty(proto.returns.as_ref());
+
+        self.u32(proto.locals.len());
+        for local in &proto.locals {
+            self.string(local);
+        }
+
+        self.u32(proto.patterns.len());
+        for pattern in &proto.patterns {
+            self.pattern(pattern)?;
+        }
+
+        let code = proto.chunk.code();
+        self.u32(code.len());
+        self.bytes(code);
+
+        let lines = proto.chunk.lines();
+        self.u32(lines.len());
+        for (offset, line) in lines {
+            self.u32(*offset);
+            self.u32(line.line());
+            self.u32(line.column());
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'r> {
+    bytes: &'r [u8],
+    pos: usize,
+}
+
+impl<'r> Reader<'r> {
+    fn take(&mut self, n: usize) -> Result<&'r [u8], FormatError> {
+        let end = self.pos.checked_add(n).ok_or(FormatError::UnexpectedEof)?;
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(FormatError::UnexpectedEof)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<usize, FormatError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn f64(&mut self) -> Result<f64, FormatError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(f64::from_be_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?;
+        let s = std::str::from_utf8(bytes).map_err(|_| FormatError::InvalidUtf8)?;
+        Ok(s.to_string())
+    }
+
+    fn value(&mut self) -> Result<Option<Value>, FormatError> {
+        let value = match self.u8()? {
+            TAG_UNSET => return Ok(None),
+            TAG_EMPTY => Value::Empty,
+            TAG_BOOLEAN => Value::Boolean(self.u8()? != 0),
+            TAG_NUMBER => Value::Number(self.f64()?),
+            TAG_STRING => Value::String(Box::new(self.string()?)),
+            TAG_NATIVE => {
+                let name = self.string()?;
+                let native = NativeFnc::ALL
+                    .into_iter()
+                    .find(|native| native.name() == name)
+                    .ok_or(FormatError::UnknownNative(name))?;
+                Value::from(native)
+            }
+            TAG_FNC => Value::from(Closure::new(self.prototype()?)),
+            TAG_ENUM => {
+                let name = self.string()?;
+                let mut variants = vec![];
+                for _ in 0..self.u32()? {
+                    let name = self.string()?;
+                    let mut fields = vec![];
+                    for _ in 0..self.u32()? {
+                        fields.push(self.ty()?.ok_or(FormatError::InvalidTag(TYPE_NONE))?);
+                    }
+                    variants.push(Variant { name, fields });
+                }
+                Value::from(EnumType { name, variants })
+            }
+            TAG_RECORD => {
+                let name = self.string()?;
+                let mut fields = vec![];
+                for _ in 0..self.u32()? {
+                    let name = self.string()?;
+                    let ty = self.ty()?.ok_or(FormatError::InvalidTag(TYPE_NONE))?;
+                    fields.push(Field { name, ty });
+                }
+                Value::from(RecordType {
+                    name,
+                    fields,
+                    methods: vec![],
+                })
+            }
+            tag => Err(FormatError::InvalidTag(tag))?,
+        };
+
+        Ok(Some(value))
+    }
+
+    fn ty(&mut self) -> Result<Option<Type>, FormatError> {
+        let ty = match self.u8()? {
+            TYPE_NONE => return Ok(None),
+            TYPE_EMPTY => Type::Empty,
+            TYPE_BOOLEAN => Type::Boolean,
+            TYPE_NUMBER => Type::Number,
+            TYPE_STRING => Type::String,
+            TYPE_LIST => Type::List,
+            TYPE_FNC => Type::Fnc,
+            TYPE_RANGE => Type::Range,
+            TYPE_MAP => Type::Map,
+            TYPE_ENUM => Type::Enum,
+            TYPE_ANY => Type::Any,
+            TYPE_NAMED => Type::Named(self.string()?),
+            tag => Err(FormatError::InvalidTag(tag))?,
+        };
+
+        Ok(Some(ty))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, FormatError> {
+        let pattern = match self.u8()? {
+            PATTERN_WILDCARD => Pattern::Wildcard,
+            PATTERN_BINDING => Pattern::Binding(self.string()?),
+            PATTERN_LITERAL => match self.value()? {
+                Some(
+                    value
+                    @ (Value::Empty | Value::Boolean(_) | Value::Number(_) | Value::String(_)),
+                ) => Pattern::Literal(value),
+                _ => Err(FormatError::InvalidBytecode(String::from(
+                    "non-literal pattern",
+                )))?,
+            },
+            PATTERN_RANGE => {
+                let (start, end, step) = (self.f64()?, self.f64()?, self.f64()?);
+                let inclusive = self.u8()? != 0;
+                let (start, end, step) = (Value::from(start), Value::from(end), Value::from(step));
+                let range = Range::new(&start, &end, &step, inclusive)
+                    .map_err(|e| FormatError::InvalidBytecode(e.to_string()))?;
+                Pattern::Range(range)
+            }
+            PATTERN_LIST => {
+                let mut items = vec![];
+                for _ in 0..self.u32()? {
+                    items.push(self.pattern()?);
+                }
+                let rest = match self.u8()? {
+                    0 => None,
+                    _ => Some(Box::new(self.pattern()?)),
+                };
+                Pattern::List { items, rest }
+            }
+            PATTERN_VARIANT => {
+                let ty = self.string()?;
+                let variant = self.string()?;
+                let mut fields = vec![];
+                for _ in 0..self.u32()? {
+                    fields.push(self.pattern()?);
+                }
+                Pattern::Variant {
+                    ty,
+                    variant,
+                    fields,
+                }
+            }
+            tag => Err(FormatError::InvalidTag(tag))?,
+        };
+
+        Ok(pattern)
+    }
+
+    fn prototype(&mut self) -> Result<Prototype, FormatError> {
+        let name = self.string()?;
+        let mut params = vec![];
+        for _ in 0..self.u32()? {
+            let ty = self.ty()?.ok_or(FormatError::InvalidTag(TYPE_NONE))?;
+            params.push(ty);
+        }
+        let required = self.u32()?;
+        let returns = self.ty()?;
+        let mut proto = Prototype::new(name, params, returns);
+        if required > proto.params.len() {
+            Err(FormatError::InvalidBytecode(format!(
+                "fnc `{}` requires more arguments than it has parameters",
+                proto.name
+            )))?
+        }
+        proto.required = required;
+
+        for _ in 0..self.u32()? {
+            let local = self.string()?;
+            proto.locals.push(local);
+        }
+        for _ in 0..self.u32()? {
+            let pattern = self.pattern()?;
+            proto.patterns.push(pattern);
+        }
+        if proto.arity() > proto.locals.len() {
+            Err(FormatError::InvalidBytecode(format!(
+                "fnc `{}` has fewer slots than parameters",
+                proto.name
+            )))?
+        }
+
+        let len = self.u32()?;
+        let code = self.take(len)?.to_vec();
+
+        let mut lines = vec![];
+        for _ in 0..self.u32()? {
+            let offset = self.u32()?;
+            let line = Line::at(self.u32()?, self.u32()?);
+            lines.push((offset, line));
+        }
+
+        proto.chunk = Chunk::from_parts(code, lines);
+        Ok(proto)
+    }
+}
+
+/// Checks that every operand in `proto` (and the functions it creates) stays within bounds,
+/// so that a damaged file is rejected here rather than crashing the VM
+fn verify(
+    memory: &Memory,
+    proto: &Prototype,
+    enclosing: &mut Vec<usize>,
+    visiting: &mut HashSet<usize>,
+) -> Result<(), FormatError> {
+    let invalid =
+        |msg: String| FormatError::InvalidBytecode(format!("{} in `{}`", msg, proto.name));
+
+    let code = proto.chunk.code();
+    let mut starts = HashSet::new();
+    let mut targets = vec![];
+    let mut last = None;
+    let mut ip = 0;
+
+    let read = |at: usize, size: usize| -> Result<usize, FormatError> {
+        let bytes = code
+            .get(at..at + size)
+            .ok_or_else(|| invalid(String::from("truncated instruction")))?;
+        Ok(bytes.iter().fold(0, |acc, b| acc << 8 | *b as usize))
+    };
+
+    while ip < code.len() {
+        starts.insert(ip);
+
+        let mut mode = AddressMode::Narrow;
+        let mut op = OpCode::try_from(code[ip]).map_err(|e| invalid(e.to_string()))?;
+        ip += 1;
+        if op == OpCode::Wide {
+            mode = AddressMode::Wide;
+            op = OpCode::try_from(read(ip, 1)? as u8).map_err(|e| invalid(e.to_string()))?;
+            ip += 1;
+            if !matches!(op.operand(), Operand::Address | Operand::Capture) {
+                Err(invalid(format!("{} cannot take a wide operand", op)))?
+            }
+        }
+
+        match op.operand() {
+            Operand::None => (),
+            Operand::Address => {
+                let addr = read(ip, mode.size())?;
+                ip += mode.size();
+
+                let in_bounds = match op {
+                    OpCode::Constant
+                    | OpCode::DefineGlobal
+                    | OpCode::GetGlobal
+                    | OpCode::SetGlobal => addr < memory.len(),
+                    OpCode::GetField | OpCode::SetField => {
+                        matches!(memory.get(addr), Ok(Value::String(_)))
+                    }
+                    OpCode::Format => matches!(
+                        memory.get(addr),
+                        Ok(Value::String(spec)) if FormatSpec::parse(&spec).is_ok()
+                    ),
+                    OpCode::Match => addr < proto.patterns.len(),
+                    OpCode::CheckParam => addr < proto.params.len(),
+                    OpCode::GetLocal | OpCode::SetLocal | OpCode::IsSet | OpCode::ClearLocal => {
+                        addr < proto.locals.len()
+                    }
+                    OpCode::Closure => {
+                        let Ok(Value::Object(obj)) = memory.get(addr) else {
+                            Err(invalid(format!("closure over non-function {:#06x}", addr)))?
+                        };
+                        let Object::Fnc(Fnc::Compiled(ref closure)) = *obj.borrow() else {
+                            Err(invalid(format!("closure over non-function {:#06x}", addr)))?
+                        };
+
+                        if !visiting.insert(addr) {
+                            Err(invalid(String::from("recursive fnc prototype")))?
+                        }
+                        enclosing.push(proto.locals.len());
+                        verify(memory, closure.proto(), enclosing, visiting)?;
+                        enclosing.pop();
+                        visiting.remove(&addr);
+                        true
+                    }
+                    _ => true,
+                };
+
+                if !in_bounds {
+                    Err(invalid(format!(
+                        "{} operand {:#06x} out of range",
+                        op, addr
+                    )))?
+                }
+            }
+            Operand::Capture => {
+                let depth = read(ip, 1)?;
+                let slot = read(ip + 1, mode.size())?;
+                ip += 1 + mode.size();
+
+                let slots = depth
+                    .checked_sub(1)
+                    .and_then(|d| enclosing.iter().rev().nth(d));
+                if slots.is_none_or(|slots| slot >= *slots) {
+                    Err(invalid(format!(
+                        "capture {}:{:#06x} out of range",
+                        depth, slot
+                    )))?
+                }
+            }
+            Operand::Jump => {
+                let offset = read(ip, 2)?;
+                ip += 2;
+                let target = if op == OpCode::Loop {
+                    ip.checked_sub(offset)
+                } else {
+                    ip.checked_add(offset)
+                };
+                targets.push(target);
+            }
+        }
+
+        last = Some(op);
+    }
+
+    if last != Some(OpCode::Return) {
+        Err(invalid(String::from("missing return")))?
+    }
+    if !targets
+        .into_iter()
+        .all(|target| target.is_some_and(|t| starts.contains(&t)))
+    {
+        Err(invalid(String::from(
+            "jump to the middle of an instruction",
+        )))?
+    }
+
+    Ok(())
+}
+
+#[test]
+fn rejects_corrupt_files() {
+    let mut umpteen = super::interpreter::Interpreter::new();
+    let mut bytes = umpteen
+        .build("fnc f(x: Number) -> Number { return x; } f(1);")
+        .unwrap();
+    assert!(load(&bytes).is_ok());
+
+    assert!(matches!(load(b"UMC"), Err(FormatError::UnexpectedEof)));
+    assert!(matches!(load(b"NOPE\0\x01"), Err(FormatError::BadMagic)));
+    assert!(matches!(
+        load(b"UMC\0\xff\xff"),
+        Err(FormatError::UnsupportedVersion(0xffff))
+    ));
+
+    let last = bytes.len() - 1;
+    bytes.truncate(last);
+    assert!(load(&bytes).is_err());
+}
@@ -0,0 +1,624 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    boxed,
+    error::{InterpretError, MemoryError, UmpteenError},
+    repr::{
+        ast::ops::Binary,
+        chunk::{OpCode, Prototype},
+        fnc::{check_args, check_arity, check_return, Call, Closure, Fnc},
+        format::FormatSpec,
+        map::{Key, Map},
+        object::Object,
+        range::Range,
+        record::RecordType,
+        value::Value,
+    },
+};
+
+use super::{
+    heap,
+    interpreter::Interpreter,
+    memory::{Address, AddressMode, AsBytes},
+};
+
+/// Storage for one local. It is moved into a shared cell once a closure captures the frame it
+/// lives in, so that the frame and the closure see each other's assignments
+#[derive(Debug, Clone)]
+pub enum Slot {
+    Plain(Option<Value>),
+    Shared(Rc<RefCell<Option<Value>>>),
+}
+
+impl Slot {
+    fn get(&self) -> Option<Value> {
+        match self {
+            Slot::Plain(value) => value.clone(),
+            Slot::Shared(cell) => cell.borrow().clone(),
+        }
+    }
+
+    fn set(&mut self, value: Value) {
+        match self {
+            Slot::Plain(var) => *var = Some(value),
+            Slot::Shared(cell) => *cell.borrow_mut() = Some(value),
+        }
+    }
+}
+
+/// Local slots of a single function activation, linked to the frame its closure was created in
+#[derive(Debug)]
+pub struct Frame {
+    proto: Rc<Prototype>,
+    slots: RefCell<Vec<Slot>>,
+    parent: Option<Rc<Frame>>,
+}
+
+impl Frame {
+    fn new(proto: Rc<Prototype>, parent: Option<Rc<Frame>>) -> Rc<Self> {
+        let frame = Rc::new(Frame {
+            slots: RefCell::new(vec![Slot::Plain(None); proto.locals.len()]),
+            proto,
+            parent,
+        });
+        heap::track_frame(&frame);
+        frame
+    }
+
+    fn load(&self, slot: usize) -> Result<Value, MemoryError> {
+        self.slots.borrow()[slot].get().ok_or_else(|| {
+            let name = self.proto.locals.get(slot).cloned().unwrap_or_default();
+            MemoryError::UninitializedVariable(name)
+        })
+    }
+
+    fn store(&self, slot: usize, value: Value) {
+        self.slots.borrow_mut()[slot].set(value);
+    }
+
+    fn clear(&self, slot: usize) {
+        self.slots.borrow_mut()[slot] = Slot::Plain(None);
+    }
+
+    /// A frame over the same storage as this one, for a closure to keep. Clearing a local here
+    /// afterwards gives it new storage, which the closure does not see
+    fn share(&self) -> Rc<Frame> {
+        let mut slots = self.slots.borrow_mut();
+        for slot in slots.iter_mut() {
+            if let Slot::Plain(value) = slot {
+                let cell = Rc::new(RefCell::new(value.take()));
+                heap::track_cell(&cell);
+                *slot = Slot::Shared(cell);
+            }
+        }
+
+        let frame = Rc::new(Frame {
+            proto: self.proto.clone(),
+            slots: RefCell::new(slots.clone()),
+            parent: self.parent.clone(),
+        });
+        heap::track_frame(&frame);
+        frame
+    }
+
+    pub(crate) fn slots(&self) -> &RefCell<Vec<Slot>> {
+        &self.slots
+    }
+
+    pub(crate) fn parent(&self) -> Option<&Rc<Frame>> {
+        self.parent.as_ref()
+    }
+
+    fn ancestor(self: &Rc<Self>, depth: usize) -> Option<Rc<Frame>> {
+        let mut frame = self.clone();
+        for _ in 0..depth {
+            frame = frame.parent.clone()?;
+        }
+        Some(frame)
+    }
+}
+
+struct CallFrame {
+    frame: Rc<Frame>,
+    ip: usize,
+    base: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Vm {
+    stack: Vec<Value>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn run(
+        &mut self,
+        interpreter: &mut Interpreter,
+        closure: Closure,
+        args: &[Value],
+    ) -> Result<Value, UmpteenError> {
+        let mut frames = vec![];
+        let mut current = Self::activate(&closure, args.to_vec(), 0)?;
+
+        // A script has no return type, and running one is not a call
+        let calls = usize::from(closure.proto().returns.is_some());
+        if calls > 0 {
+            interpreter.enter()?;
+        }
+        let result = self.execute(interpreter, &mut frames, &mut current);
+        interpreter.leave(calls + frames.len());
+
+        result.map_err(|e| {
+            let offset = current.ip.saturating_sub(1);
+            match current.frame.proto.chunk.line_at(offset) {
+                Some(line) => e.located(line),
+                None => e,
+            }
+        })
+    }
+
+    fn execute(
+        &mut self,
+        interpreter: &mut Interpreter,
+        frames: &mut Vec<CallFrame>,
+        current: &mut CallFrame,
+    ) -> Result<Value, UmpteenError> {
+        macro_rules! read_byte {
+            () => {{
+                let byte = current.frame.proto.chunk.code()[current.ip];
+                current.ip += 1;
+                byte
+            }};
+        }
+
+        macro_rules! read_address {
+            ($mode:expr) => {{
+                let addr = match $mode {
+                    AddressMode::Narrow => Address::try_from_bytes([read_byte!()])?,
+                    AddressMode::Wide => Address::try_from_bytes([read_byte!(), read_byte!()])?,
+                };
+                addr.read()
+            }};
+        }
+
+        macro_rules! read_jump {
+            () => {
+                u16::from_be_bytes([read_byte!(), read_byte!()]) as usize
+            };
+        }
+
+        macro_rules! binary {
+            ($op:tt) => {{
+                let rhs = self.pop();
+                let lhs = self.pop();
+                self.stack.push((lhs $op rhs)?);
+            }};
+        }
+
+        macro_rules! compare {
+            ($op:tt) => {{
+                let rhs = self.pop();
+                let lhs = self.pop();
+                self.stack.push(lhs.compare(&rhs, Binary::$op)?);
+            }};
+        }
+
+        loop {
+            let mut mode = AddressMode::Narrow;
+            let mut op = OpCode::try_from(read_byte!())?;
+            if op == OpCode::Wide {
+                mode = AddressMode::Wide;
+                op = OpCode::try_from(read_byte!())?;
+            }
+
+            match op {
+                OpCode::Wide => unreachable!(),
+
+                OpCode::Constant => {
+                    let addr = read_address!(mode);
+                    let value = interpreter.memory().get(addr)?;
+                    self.stack.push(value);
+                }
+                OpCode::Empty => self.stack.push(Value::Empty),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::Duplicate => {
+                    let count = read_address!(mode);
+                    let top = self.stack.len().saturating_sub(count);
+                    self.stack.extend_from_within(top..);
+                }
+
+                OpCode::DefineGlobal => {
+                    let addr = read_address!(mode);
+                    let value = self.pop();
+                    interpreter.memory_mut()[addr] = Some(value);
+                }
+                OpCode::GetGlobal => {
+                    let addr = read_address!(mode);
+                    let memory = interpreter.memory();
+                    match memory.get(addr) {
+                        Ok(value) => self.stack.push(value),
+                        Err(_) => {
+                            let name = memory.name_of(addr).unwrap_or_default().to_string();
+                            if memory.is_declared(addr) {
+                                Err(MemoryError::UninitializedVariable(name))?
+                            } else {
+                                Err(MemoryError::NoSuchVariable(name))?
+                            }
+                        }
+                    }
+                }
+                OpCode::SetGlobal => {
+                    let addr = read_address!(mode);
+                    let value = self.pop();
+                    let memory = interpreter.memory_mut();
+                    let name = || memory.name_of(addr).unwrap_or_default().to_string();
+                    if !memory.is_declared(addr) {
+                        Err(MemoryError::NoSuchVariable(name()))?
+                    }
+                    if !memory.is_mutable(addr) && memory[addr].is_some() {
+                        Err(MemoryError::AssignToImmutable(name()))?
+                    }
+                    memory[addr] = Some(value);
+                }
+                OpCode::GetLocal => {
+                    let slot = read_address!(mode);
+                    let value = current.frame.load(slot)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal => {
+                    let slot = read_address!(mode);
+                    let value = self.pop();
+                    current.frame.store(slot, value);
+                }
+                OpCode::IsSet => {
+                    let slot = read_address!(mode);
+                    let set = current.frame.slots.borrow()[slot].get().is_some();
+                    self.stack.push(Value::Boolean(set));
+                }
+                OpCode::CheckParam => {
+                    let slot = read_address!(mode);
+                    let proto = &current.frame.proto;
+                    let param = proto.locals.iter().zip(&proto.params).skip(slot).take(1);
+                    check_args(&proto.name, param, &[current.frame.load(slot)?])?;
+                }
+                OpCode::ClearLocal => {
+                    let slot = read_address!(mode);
+                    current.frame.clear(slot);
+                }
+                OpCode::GetCapture => {
+                    let depth = read_byte!() as usize;
+                    let slot = read_address!(mode);
+                    let frame = Self::capture(&current.frame, depth)?;
+                    let value = frame.load(slot)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetCapture => {
+                    let depth = read_byte!() as usize;
+                    let slot = read_address!(mode);
+                    let frame = Self::capture(&current.frame, depth)?;
+                    let value = self.pop();
+                    frame.store(slot, value);
+                }
+
+                OpCode::List => {
+                    let count = read_address!(mode);
+                    let values = self.stack.split_off(self.stack.len().saturating_sub(count));
+                    self.stack.push(Value::Object(Object::list(values)));
+                }
+                OpCode::Map => {
+                    let count = read_address!(mode);
+                    let values = self
+                        .stack
+                        .split_off(self.stack.len().saturating_sub(count * 2));
+                    let mut map = Map::new();
+                    for entry in values.chunks(2) {
+                        map.insert(Key::try_from(&entry[0])?, entry[1].clone());
+                    }
+                    self.stack.push(Value::Object(Object::map(map)));
+                }
+                OpCode::Range | OpCode::RangeInclusive => {
+                    let step = self.pop();
+                    let end = self.pop();
+                    let start = self.pop();
+                    let inclusive = op == OpCode::RangeInclusive;
+                    let range = Range::new(&start, &end, &step, inclusive)?;
+                    self.stack.push(Value::Object(Object::range(range)));
+                }
+                OpCode::GetIndex => {
+                    let idx = self.pop();
+                    let target = self.pop();
+                    self.stack.push(target.index(&idx)?);
+                }
+                OpCode::Iterable => {
+                    let iterable = self.pop().iterable();
+                    self.stack.push(iterable);
+                }
+                OpCode::Iterate => {
+                    let position = self.pop();
+                    let iterable = self.pop();
+                    let Value::Number(position) = position else {
+                        Err(MemoryError::CannotIndexWith(iterable.to_string()))?
+                    };
+                    match iterable.iterate(position as usize)? {
+                        Some(item) => {
+                            self.stack.push(item);
+                            self.stack.push(Value::Boolean(true));
+                        }
+                        None => self.stack.push(Value::Boolean(false)),
+                    }
+                }
+                OpCode::Slice => {
+                    let step = self.pop();
+                    let end = self.pop();
+                    let start = self.pop();
+                    let target = self.pop();
+                    self.stack.push(target.slice_by(&start, &end, &step)?);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let idx = self.pop();
+                    let mut target = self.pop();
+                    target.set_index(&idx, value)?;
+                }
+                OpCode::Match => {
+                    let addr = read_address!(mode);
+                    let pattern = &current.frame.proto.patterns[addr];
+                    let subject = self.pop();
+                    let mut bound = vec![];
+                    let matched = pattern.matches(&subject, &mut bound);
+                    if matched {
+                        self.stack.extend(bound);
+                    }
+                    self.stack.push(Value::Boolean(matched));
+                }
+                OpCode::NoMatch => {
+                    let subject = self.pop();
+                    Err(InterpretError::NoMatch(subject.to_string()))?
+                }
+                OpCode::GetField => {
+                    let field = interpreter.memory().get(read_address!(mode))?.to_string();
+                    let object = self.pop();
+                    self.stack.push(object.get_field(&field)?);
+                }
+                OpCode::SetField => {
+                    let field = interpreter.memory().get(read_address!(mode))?.to_string();
+                    let value = self.pop();
+                    let object = self.pop();
+                    object.set_field(&field, value)?;
+                }
+                OpCode::Format => {
+                    let spec = interpreter.memory().get(read_address!(mode))?.to_string();
+                    let value = self.pop();
+                    let string = FormatSpec::parse(&spec)?.apply(&value);
+                    self.stack.push(Value::String(boxed!(string)));
+                }
+                OpCode::Methods => {
+                    let count = read_address!(mode);
+                    let pairs = self
+                        .stack
+                        .split_off(self.stack.len().saturating_sub(count * 2));
+                    let constructor = self.pop();
+
+                    let mut methods = vec![];
+                    for pair in pairs.chunks(2) {
+                        let (Value::String(name), Value::Object(obj)) = (&pair[0], &pair[1]) else {
+                            Err(InterpretError::TriedToCallNonFunction(pair[1].to_string()))?
+                        };
+                        let Object::Fnc(ref fnc) = *obj.borrow() else {
+                            Err(InterpretError::TriedToCallNonFunction(pair[1].to_string()))?
+                        };
+                        methods.push((name.to_string(), fnc.clone()));
+                    }
+
+                    let Value::Object(ref obj) = constructor else {
+                        Err(InterpretError::TriedToCallNonFunction(
+                            constructor.to_string(),
+                        ))?
+                    };
+                    let Object::Fnc(Fnc::Record(ref ty)) = *obj.borrow() else {
+                        Err(InterpretError::TriedToCallNonFunction(
+                            constructor.to_string(),
+                        ))?
+                    };
+                    let ty = RecordType {
+                        name: ty.name.clone(),
+                        fields: ty.fields.clone(),
+                        methods,
+                    };
+                    self.stack.push(Value::from(ty));
+                }
+
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(!value);
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    self.stack.push((-value)?);
+                }
+                OpCode::Add => binary!(+),
+                OpCode::Subtract => binary!(-),
+                OpCode::Multiply => binary!(*),
+                OpCode::Divide => binary!(/),
+                OpCode::Modulo => binary!(%),
+                OpCode::Equal => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    self.stack.push(Value::Boolean(lhs == rhs));
+                }
+                OpCode::NotEqual => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    self.stack.push(Value::Boolean(lhs != rhs));
+                }
+                OpCode::Is => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    self.stack.push(Value::Boolean(lhs.is(&rhs)));
+                }
+                OpCode::In => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    self.stack.push(rhs.contains(&lhs)?);
+                }
+                OpCode::Greater => compare!(GreaterThan),
+                OpCode::GreaterEqual => compare!(GreaterOrEqual),
+                OpCode::Less => compare!(LessThan),
+                OpCode::LessEqual => compare!(LessOrEqual),
+
+                OpCode::Jump => {
+                    let offset = read_jump!();
+                    current.ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = read_jump!();
+                    if !self.peek().truthy() {
+                        current.ip += offset;
+                    }
+                }
+                OpCode::JumpIfTrue => {
+                    let offset = read_jump!();
+                    if self.peek().truthy() {
+                        current.ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = read_jump!();
+                    current.ip -= offset;
+                }
+
+                OpCode::Closure => {
+                    let addr = read_address!(mode);
+                    let value = interpreter.memory().get(addr)?;
+                    let closure = match value {
+                        Value::Object(ref obj) => match *obj.borrow() {
+                            Object::Fnc(Fnc::Compiled(ref proto)) => {
+                                proto.capture(current.frame.share())
+                            }
+                            _ => Err(MemoryError::InvalidReference(addr))?,
+                        },
+                        _ => Err(MemoryError::InvalidReference(addr))?,
+                    };
+                    self.stack.push(Value::from(closure));
+                }
+                OpCode::Call => {
+                    let argc = read_address!(mode);
+                    let args = self.stack.split_off(self.stack.len().saturating_sub(argc));
+                    let callee = self.pop();
+
+                    let Value::Object(ref obj) = callee else {
+                        Err(InterpretError::TriedToCallNonFunction(callee.to_string()))?
+                    };
+                    let Object::Fnc(ref fnc) = *obj.borrow() else {
+                        Err(InterpretError::TriedToCallNonFunction(callee.to_string()))?
+                    };
+
+                    match fnc.clone() {
+                        Fnc::Compiled(closure) => {
+                            let base = self.stack.len();
+                            let callee = Self::activate(&closure, args, base)?;
+                            interpreter.enter()?;
+                            frames.push(std::mem::replace(current, callee));
+                        }
+                        Fnc::Bound(receiver, method) if matches!(*method, Fnc::Compiled(_)) => {
+                            check_arity(fnc, args.len())?;
+                            let Fnc::Compiled(closure) = *method else {
+                                unreachable!()
+                            };
+                            let args = [vec![receiver], args].concat();
+                            let base = self.stack.len();
+                            let callee = Self::activate(&closure, args, base)?;
+                            interpreter.enter()?;
+                            frames.push(std::mem::replace(current, callee));
+                        }
+                        mut fnc => {
+                            check_arity(&fnc, args.len())?;
+                            let value = fnc.call(interpreter, &args)?;
+                            self.stack.push(value);
+                        }
+                    }
+                }
+                OpCode::Return => {
+                    let value = self.pop();
+                    let proto = &current.frame.proto;
+                    if let Some(returns) = &proto.returns {
+                        check_return(&proto.name, returns, &value)?;
+                    }
+                    self.stack.truncate(current.base);
+
+                    let Some(caller) = frames.pop() else {
+                        return Ok(value);
+                    };
+                    interpreter.leave(1);
+                    *current = caller;
+                    self.stack.push(value);
+                }
+            }
+        }
+    }
+
+    fn activate(
+        closure: &Closure,
+        args: Vec<Value>,
+        base: usize,
+    ) -> Result<CallFrame, InterpretError> {
+        let proto = closure.proto();
+        check_arity(closure, args.len())?;
+        check_args(&proto.name, proto.locals.iter().zip(&proto.params), &args)?;
+
+        let frame = Frame::new(proto.clone(), closure.env().cloned());
+        for (slot, arg) in args.into_iter().take(proto.arity()).enumerate() {
+            frame.store(slot, arg);
+        }
+
+        Ok(CallFrame { frame, ip: 0, base })
+    }
+
+    fn capture(frame: &Rc<Frame>, depth: usize) -> Result<Rc<Frame>, MemoryError> {
+        frame
+            .ancestor(depth)
+            .ok_or(MemoryError::OutOfBoundsMemoryAccess)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or_default()
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().unwrap_or(&Value::Empty)
+    }
+}
+
+#[test]
+fn bytecode_matches_tree_walk() {
+    let src = "var total = 0; var i = 0; loop { i += 1; if i > 10 { break; } total += i; } total;";
+
+    let expected = Interpreter::new().run(src).unwrap();
+    let actual = Interpreter::with_backend(super::interpreter::Backend::Bytecode)
+        .run(src)
+        .unwrap();
+
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn for_and_while_loops_match_tree_walk() {
+    let src = "var s = \"\"; for i, c in \"abc\" { if i == 1 { continue; } s += c; }
+        var n = 0; while n < 10 { n += 1; if n == 5 { break; } }
+        for x in [1, 2] { s += str(x + n); } s;";
+
+    let expected = Interpreter::new().run(src).unwrap();
+    let actual = Interpreter::with_backend(super::interpreter::Backend::Bytecode)
+        .run(src)
+        .unwrap();
+
+    assert_eq!(expected, Value::from("ac67"));
+    assert_eq!(actual, expected);
+}
@@ -1,5 +1,12 @@
 pub mod ast;
+pub mod chunk;
+pub mod enumeration;
 pub mod fnc;
+pub mod format;
+pub mod map;
 pub mod object;
+pub mod range;
+pub mod record;
 pub mod token;
+pub mod types;
 pub mod value;
@@ -1,3 +1,4 @@
 pub mod expr;
 pub mod ops;
+pub mod pattern;
 pub mod stmt;
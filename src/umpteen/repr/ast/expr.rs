@@ -1,16 +1,54 @@
-use crate::{boxed, repr::value::Value};
+use crate::{
+    boxed,
+    repr::{format::FormatSpec, types::Type, value::Value},
+};
 
-use super::ops::{Binary, Unary};
+use super::{
+    ops::{Binary, Unary},
+    pattern::Pattern,
+    stmt::{Param, Stmt},
+};
 
 pub type SubExpr = Box<Expr>;
 
+/// Position of a local binding, filled in by the resolver. `depth` counts scopes outward from
+/// the use site and `slot` is the order of declaration within that scope
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Local {
+    pub depth: usize,
+    pub slot: usize,
+}
+
+/// What a name refers to, filled in by the resolver. A global is told apart from the ones it
+/// shadows by how many times its name was declared before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolved {
+    Local(Local),
+    Global(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+/// A piece of an interpolated string literal
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Value(Expr, FormatSpec),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Literal(Value),
+    Interpolation(Vec<Segment>), // Concatenates each segment as written by `str`
     List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
     Binding {
         name: String,
-        index: Option<SubExpr>,
+        resolved: Resolved,
     },
     Grouping {
         expr: SubExpr,
@@ -26,13 +64,54 @@ pub enum Expr {
     },
     Assign {
         name: String,
-        index: Option<SubExpr>,
         expr: SubExpr,
+        resolved: Resolved,
     },
     Call {
         callee: SubExpr,
         args: Vec<Expr>,
     },
+    Get {
+        object: SubExpr,
+        field: String,
+    },
+    Set {
+        object: SubExpr,
+        field: String,
+        op: Option<Binary>, // Combines the current value with `expr` for compound assignment
+        expr: SubExpr,
+    },
+    Index {
+        object: SubExpr,
+        index: SubExpr,
+    },
+    Slice {
+        object: SubExpr,
+        start: Option<SubExpr>, // Each part takes its default when omitted
+        end: Option<SubExpr>,
+        step: Option<SubExpr>,
+    },
+    SetIndex {
+        object: SubExpr,
+        index: SubExpr,
+        op: Option<Binary>,
+        expr: SubExpr,
+    },
+    Lambda {
+        params: Vec<Param>,
+        returns: Type,
+        body: Vec<Stmt>,
+    },
+    Match {
+        subject: SubExpr,
+        arms: Vec<Arm>,
+    },
+    Range {
+        start: SubExpr,
+        end: SubExpr,
+        step: Option<SubExpr>, // Counts up by one when omitted
+        inclusive: bool,
+    },
 }
 
 impl Expr {
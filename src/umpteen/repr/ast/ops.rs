@@ -29,6 +29,8 @@ pub enum Binary {
     Or,
     Equality,
     Inequality,
+    Identity,
+    Membership,
     GreaterThan,
     GreaterOrEqual,
     LessThan,
@@ -48,6 +50,8 @@ impl TryFrom<TokenType> for Binary {
         let op = match value {
             TokenType::EqualEqual => Self::Equality,
             TokenType::BangEqual => Self::Inequality,
+            TokenType::Is => Self::Identity,
+            TokenType::In => Self::Membership,
             TokenType::Greater => Self::GreaterThan,
             TokenType::GreaterEqual => Self::GreaterOrEqual,
             TokenType::Less => Self::LessThan,
@@ -92,6 +96,8 @@ impl Display for Binary {
             Binary::Or => "logical OR",
             Binary::Equality => "equality",
             Binary::Inequality => "inequality",
+            Binary::Identity => "identity",
+            Binary::Membership => "membership",
             Binary::GreaterThan => "greater than",
             Binary::GreaterOrEqual => "greater than or equal to",
             Binary::LessThan => "less than",
@@ -0,0 +1,101 @@
+use crate::repr::{object::Object, range::Range, value::Value};
+
+/// The left-hand side of a `match` arm
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Binding(String),
+    Literal(Value),
+    Range(Range),
+    List {
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>, // Matches whatever follows the items, written `..rest`
+    },
+    Variant {
+        ty: String,
+        variant: String,
+        fields: Vec<Pattern>,
+    },
+}
+
+impl Pattern {
+    /// Tests `value` against the pattern, pushing the values it binds in the order of `bindings`
+    pub fn matches(&self, value: &Value, bound: &mut Vec<Value>) -> bool {
+        match self {
+            Pattern::Wildcard => true,
+            Pattern::Binding(_) => {
+                bound.push(value.clone());
+                true
+            }
+            Pattern::Literal(literal) => value == literal,
+            Pattern::Range(range) => matches!(value, Value::Number(x) if range.contains(*x)),
+            Pattern::List { items, rest } => {
+                let Value::Object(obj) = value else {
+                    return false;
+                };
+                let Object::List(ref list) = *obj.borrow() else {
+                    return false;
+                };
+
+                let fits = match rest {
+                    Some(_) => list.len() >= items.len(),
+                    None => list.len() == items.len(),
+                };
+                if !fits
+                    || !items
+                        .iter()
+                        .zip(list.iter())
+                        .all(|(p, x)| p.matches(x, bound))
+                {
+                    return false;
+                }
+
+                match rest {
+                    Some(rest) => {
+                        let tail = Value::Object(Object::list(list[items.len()..].to_vec()));
+                        rest.matches(&tail, bound)
+                    }
+                    None => true,
+                }
+            }
+            Pattern::Variant {
+                ty,
+                variant,
+                fields,
+            } => {
+                let Value::Object(obj) = value else {
+                    return false;
+                };
+                let Object::Variant(ref instance) = *obj.borrow() else {
+                    return false;
+                };
+
+                instance.ty().name == *ty
+                    && instance.name() == variant
+                    && fields
+                        .iter()
+                        .zip(instance.values())
+                        .all(|(p, x)| p.matches(x, bound))
+            }
+        }
+    }
+
+    /// Names bound by the pattern, from left to right
+    pub fn bindings(&self) -> Vec<&str> {
+        match self {
+            Pattern::Binding(name) => vec![name],
+            Pattern::List { items, rest } => items
+                .iter()
+                .chain(rest.as_deref())
+                .flat_map(Pattern::bindings)
+                .collect(),
+            Pattern::Variant { fields, .. } => fields.iter().flat_map(Pattern::bindings).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether every value matches the pattern
+    pub fn irrefutable(&self) -> bool {
+        matches!(self, Pattern::Wildcard | Pattern::Binding(_))
+    }
+}
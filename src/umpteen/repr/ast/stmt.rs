@@ -1,13 +1,56 @@
+use crate::{error::Line, repr::types::Type};
+
 use super::expr::Expr;
 
 pub type SubStmt = Box<Stmt>;
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: Line,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, line: Line) -> Self {
+        Stmt { kind, line }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+    pub default: Option<Expr>, // Evaluated at call time when the argument is omitted
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+}
+
+/// A function declared inside a record type, called on an instance that it receives as `self`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Method {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub returns: Type,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Type>, // Payload types, empty for unit variants
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
     Expr(Expr),
     Declare {
         name: String,
         init: Option<Expr>,
+        mutable: bool,
     },
     Block(Vec<Stmt>),
     Condition {
@@ -16,13 +59,33 @@ pub enum Stmt {
         else_branch: Option<Vec<Stmt>>,
     },
     Loop(Vec<Stmt>),
+    While {
+        test: Expr,
+        body: Vec<Stmt>,
+    },
+    For {
+        index: Option<String>,
+        item: String,
+        iterable: Expr,
+        body: Vec<Stmt>,
+    },
     Break,
     Continue,
     Return(Expr),
     Fnc {
         name: String,
-        params: Vec<(String, String)>, // TODO: Second tuple value should be Type instead of String
+        params: Vec<Param>,
+        returns: Type,
         body: Vec<Stmt>,
     },
+    Type {
+        name: String,
+        fields: Vec<Field>,
+        methods: Vec<Method>,
+    },
+    Enum {
+        name: String,
+        variants: Vec<Variant>,
+    },
     Exit,
 }
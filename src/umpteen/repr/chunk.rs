@@ -0,0 +1,386 @@
+use std::fmt::Display;
+
+use crate::{
+    error::{CompilerError, Line},
+    exec::memory::{Address, AddressMode, AsBytes},
+};
+
+use super::{ast::pattern::Pattern, types::Type};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    Wide, // Widens the operand of the following instruction to two bytes
+
+    Constant,
+    Empty,
+    True,
+    False,
+    Pop,
+    Duplicate, // Pushes copies of the given number of values from the top of the stack
+
+    DefineGlobal,
+    GetGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
+    IsSet,      // Whether a local slot holds a value yet, used for parameter defaults
+    CheckParam, // Checks a parameter filled in by its default against the parameter's type
+    ClearLocal, // Gives a local slot new, empty storage, leaving the old to any closure holding it
+    GetCapture,
+    SetCapture,
+
+    List,
+    Map,
+    Range,
+    RangeInclusive,
+    GetIndex,
+    SetIndex,
+    Slice, // Pops a start, end and step, each of which may be Empty, and the value to slice
+
+    GetField, // Operands name their field by the address of a String constant
+    SetField,
+    Methods, // Attaches the given number of name and method pairs to the record type below them
+    Format,  // Writes a value as a String, by the spec in the String constant its operand addresses
+    Iterable, // Replaces the value a `for` loop is about to walk with what it visits, once
+    Iterate, // Pushes the next item of a `for` loop and true, or just false once it runs out
+
+    Not,
+    Negate,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Is,
+    In,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+
+    Jump,
+    JumpIfFalse,
+    JumpIfTrue,
+    Loop,
+
+    Match, // Pushes the values bound by one of the function's patterns and true, or just false
+    NoMatch,
+
+    Closure,
+    Call,
+    Return,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    None,
+    Address, // Narrow or wide, depending on a preceding `Wide`
+    Capture, // One byte of depth followed by an address
+    Jump,    // Always two bytes
+}
+
+impl OpCode {
+    const ALL: [OpCode; 54] = [
+        OpCode::Wide,
+        OpCode::Constant,
+        OpCode::Empty,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::Duplicate,
+        OpCode::DefineGlobal,
+        OpCode::GetGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::IsSet,
+        OpCode::CheckParam,
+        OpCode::ClearLocal,
+        OpCode::GetCapture,
+        OpCode::SetCapture,
+        OpCode::List,
+        OpCode::Map,
+        OpCode::Range,
+        OpCode::RangeInclusive,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::Slice,
+        OpCode::GetField,
+        OpCode::SetField,
+        OpCode::Methods,
+        OpCode::Format,
+        OpCode::Iterable,
+        OpCode::Iterate,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Modulo,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Is,
+        OpCode::In,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::JumpIfTrue,
+        OpCode::Loop,
+        OpCode::Match,
+        OpCode::NoMatch,
+        OpCode::Closure,
+        OpCode::Call,
+        OpCode::Return,
+    ];
+
+    pub const fn operand(&self) -> Operand {
+        match self {
+            OpCode::Constant
+            | OpCode::Duplicate
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::IsSet
+            | OpCode::CheckParam
+            | OpCode::ClearLocal
+            | OpCode::List
+            | OpCode::Map
+            | OpCode::GetField
+            | OpCode::SetField
+            | OpCode::Methods
+            | OpCode::Format
+            | OpCode::Match
+            | OpCode::Closure
+            | OpCode::Call => Operand::Address,
+
+            OpCode::GetCapture | OpCode::SetCapture => Operand::Capture,
+
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfTrue | OpCode::Loop => Operand::Jump,
+
+            _ => Operand::None,
+        }
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = CompilerError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .get(value as usize)
+            .copied()
+            .ok_or(CompilerError::InvalidOpCode(value))
+    }
+}
+
+impl Display for OpCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    code: Vec<u8>,
+    lines: Vec<(usize, Line)>, // Offset of the first instruction on each source line
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_parts(code: Vec<u8>, lines: Vec<(usize, Line)>) -> Self {
+        Chunk { code, lines }
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    pub fn lines(&self) -> &[(usize, Line)] {
+        &self.lines
+    }
+
+    /// Attributes the instructions written from here on to `line`
+    pub fn mark_line(&mut self, line: Line) {
+        if self.lines.last().map(|(_, last)| *last) != Some(line) {
+            self.lines.push((self.len(), line));
+        }
+    }
+
+    pub fn line_at(&self, offset: usize) -> Option<Line> {
+        let idx = self.lines.partition_point(|(start, _)| *start <= offset);
+        self.lines.get(idx.checked_sub(1)?).map(|(_, line)| *line)
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    pub fn write(&mut self, op: OpCode) {
+        self.code.push(op as u8);
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        self.code.push(byte);
+    }
+
+    /// Writes `op` followed by `addr`, prefixing the instruction with `Wide` when needed
+    pub fn write_address(&mut self, op: OpCode, addr: usize) -> Result<(), CompilerError> {
+        let addr = Address::new(addr)?;
+        if addr.mode() == AddressMode::Wide {
+            self.write(OpCode::Wide);
+        }
+        self.write(op);
+        self.write_operand(addr);
+        Ok(())
+    }
+
+    /// Writes `op` followed by the depth of the enclosing frame and a slot within it
+    pub fn write_capture(
+        &mut self,
+        op: OpCode,
+        depth: u8,
+        slot: usize,
+    ) -> Result<(), CompilerError> {
+        let addr = Address::new(slot)?;
+        if addr.mode() == AddressMode::Wide {
+            self.write(OpCode::Wide);
+        }
+        self.write(op);
+        self.write_byte(depth);
+        self.write_operand(addr);
+        Ok(())
+    }
+
+    pub fn write_operand(&mut self, addr: Address) {
+        match addr.mode() {
+            AddressMode::Narrow => self.code.extend(AsBytes::<1>::to_bytes(addr)),
+            AddressMode::Wide => self.code.extend(AsBytes::<2>::to_bytes(addr)),
+        }
+    }
+
+    /// Emits a forward jump with a placeholder offset, returning the position to patch
+    pub fn write_jump(&mut self, op: OpCode) -> usize {
+        self.write(op);
+        self.code.extend([0xff, 0xff]);
+        self.len() - 2
+    }
+
+    pub fn patch_jump(&mut self, at: usize) -> Result<(), CompilerError> {
+        let distance = self.len() - at - 2;
+        let offset = u16::try_from(distance).map_err(|_| CompilerError::JumpTooLarge(distance))?;
+        self.code[at..at + 2].copy_from_slice(&offset.to_be_bytes());
+        Ok(())
+    }
+
+    pub fn write_loop(&mut self, start: usize) -> Result<(), CompilerError> {
+        self.write(OpCode::Loop);
+        let distance = self.len() - start + 2;
+        let offset = u16::try_from(distance).map_err(|_| CompilerError::JumpTooLarge(distance))?;
+        self.code.extend(offset.to_be_bytes());
+        Ok(())
+    }
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ip = 0;
+        let mut mode = AddressMode::Narrow;
+
+        while ip < self.code.len() {
+            let Ok(op) = OpCode::try_from(self.code[ip]) else {
+                writeln!(f, "{:04} ??? {:#04x}", ip, self.code[ip])?;
+                ip += 1;
+                continue;
+            };
+
+            if op == OpCode::Wide {
+                mode = AddressMode::Wide;
+                ip += 1;
+                continue;
+            }
+
+            let at = ip;
+            ip += 1;
+
+            let read = |ip: usize, size: usize| -> usize {
+                self.code.get(ip..ip + size).map_or(0, |bytes| {
+                    bytes.iter().fold(0, |acc, b| acc << 8 | *b as usize)
+                })
+            };
+
+            match op.operand() {
+                Operand::None => writeln!(f, "{:04} {}", at, op)?,
+                Operand::Address => {
+                    writeln!(f, "{:04} {} {:#06x}", at, op, read(ip, mode.size()))?;
+                    ip += mode.size();
+                }
+                Operand::Capture => {
+                    let depth = read(ip, 1);
+                    let slot = read(ip + 1, mode.size());
+                    writeln!(f, "{:04} {} {}:{:#06x}", at, op, depth, slot)?;
+                    ip += 1 + mode.size();
+                }
+                Operand::Jump => {
+                    let offset = read(ip, 2);
+                    ip += 2;
+                    let target = if op == OpCode::Loop {
+                        ip.saturating_sub(offset)
+                    } else {
+                        ip + offset
+                    };
+                    writeln!(f, "{:04} {} -> {:04}", at, op, target)?;
+                }
+            }
+
+            mode = AddressMode::Narrow;
+        }
+
+        Ok(())
+    }
+}
+
+/// A compiled function body, stored in the constant pool and instantiated by `OpCode::Closure`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prototype {
+    pub name: String,
+    pub params: Vec<Type>,
+    pub required: usize,       // Parameters after the required ones have defaults
+    pub returns: Option<Type>, // None for scripts, whose result is never checked
+    pub locals: Vec<String>,   // Slot names, parameters first
+    pub patterns: Vec<Pattern>, // Tried by `OpCode::Match`
+    pub chunk: Chunk,
+}
+
+impl Prototype {
+    pub fn new(name: String, params: Vec<Type>, returns: Option<Type>) -> Self {
+        Prototype {
+            name,
+            required: params.len(),
+            params,
+            returns,
+            locals: vec![],
+            patterns: vec![],
+            chunk: Chunk::new(),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
@@ -0,0 +1,82 @@
+use std::{fmt::Display, rc::Rc};
+
+use crate::error::InterpretError;
+
+use super::{ast::stmt::Variant, object::Object, types::Type, value::Value};
+
+/// An enum declared with `enum`. Its variants are reached as fields of the enum itself
+#[derive(Debug, PartialEq)]
+pub struct EnumType {
+    pub name: String,
+    pub variants: Vec<Variant>,
+}
+
+impl EnumType {
+    pub fn tag(&self, variant: &str) -> Option<usize> {
+        self.variants.iter().position(|v| v.name == variant)
+    }
+
+    /// Builds an instance of the variant at `tag`, checking the payload against its types
+    pub fn construct(
+        self: &Rc<Self>,
+        tag: usize,
+        values: &[Value],
+    ) -> Result<Value, InterpretError> {
+        let variant = &self.variants[tag];
+        for (i, (expected, got)) in variant.fields.iter().zip(values).enumerate() {
+            if !expected.admits(got) {
+                Err(InterpretError::FieldTypeMismatch {
+                    record: format!("{}.{}", self.name, variant.name),
+                    field: i.to_string(),
+                    expected: expected.clone(),
+                    got: Type::of(got),
+                })?
+            }
+        }
+
+        let instance = Instance {
+            ty: self.clone(),
+            tag,
+            values: values.to_vec(),
+        };
+        Ok(Value::Object(Object::variant(instance)))
+    }
+}
+
+/// A value of one of an enum's variants, along with its payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    ty: Rc<EnumType>,
+    tag: usize,
+    values: Vec<Value>,
+}
+
+impl Instance {
+    pub fn ty(&self) -> &Rc<EnumType> {
+        &self.ty
+    }
+
+    pub fn name(&self) -> &str {
+        &self.ty.variants[self.tag].name
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+impl Display for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.ty.name, self.name())?;
+        if !self.values.is_empty() {
+            let values: Vec<_> = self.values.iter().map(Value::to_string).collect();
+            write!(f, "({})", values.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
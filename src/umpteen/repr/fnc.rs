@@ -1,24 +1,276 @@
-use std::fmt::Display;
+use std::{cell::RefMut, fmt::Display, rc::Rc};
 
-use crate::{error::UmpteenError, exec::interpreter::Interpreter};
+use unicode_segmentation::UnicodeSegmentation;
 
-use super::{ast::stmt::Stmt, object::Object, value::Value};
+use crate::{
+    error::{InterpretError, UmpteenError},
+    exec::{
+        env::Scope,
+        heap,
+        interpreter::{Divergence, Interpreter},
+        vm::{Frame, Vm},
+    },
+};
+
+use super::{
+    ast::stmt::{Param, Stmt},
+    chunk::Prototype,
+    enumeration::EnumType,
+    map::{Key, Map},
+    object::Object,
+    record::{Record, RecordType},
+    types::Type,
+    value::Value,
+};
 
 pub trait Call {
     fn call(&mut self, vm: &mut Interpreter, args: &[Value]) -> Result<Value, UmpteenError>;
-    fn arity(&self) -> usize;
+    fn arity(&self) -> Arity;
     fn name(&self) -> String;
 }
 
+/// Number of arguments a function accepts, with no upper bound for variadic functions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn fixed(n: usize) -> Self {
+        Arity {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    pub fn range(min: usize, max: usize) -> Self {
+        Arity {
+            min,
+            max: Some(max),
+        }
+    }
+
+    pub fn variadic(min: usize) -> Self {
+        Arity { min, max: None }
+    }
+
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.min && self.max.is_none_or(|max| n <= max)
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+pub fn check_arity(fnc: &impl Call, got: usize) -> Result<(), InterpretError> {
+    let expected = fnc.arity();
+    if !expected.accepts(got) {
+        Err(InterpretError::ArityMismatch {
+            name: fnc.name(),
+            expected,
+            got,
+        })?
+    }
+
+    Ok(())
+}
+
+/// Checks each argument against the annotated type of its parameter
+pub fn check_args<'p>(
+    fnc: &str,
+    params: impl IntoIterator<Item = (&'p String, &'p Type)>,
+    args: &[Value],
+) -> Result<(), InterpretError> {
+    for ((param, expected), arg) in params.into_iter().zip(args) {
+        if !expected.admits(arg) {
+            Err(InterpretError::ArgumentTypeMismatch {
+                fnc: fnc.to_string(),
+                param: param.to_string(),
+                expected: expected.clone(),
+                got: Type::of(arg),
+            })?
+        }
+    }
+
+    Ok(())
+}
+
+pub fn check_return(fnc: &str, expected: &Type, value: &Value) -> Result<(), InterpretError> {
+    if !expected.admits(value) {
+        Err(InterpretError::ReturnTypeMismatch {
+            fnc: fnc.to_string(),
+            expected: expected.clone(),
+            got: Type::of(value),
+        })?
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum NativeFnc {
-    Time,   // Returns a numeric representation of the current time
-    Print,  // Print a line to stdout
-    Printx, // Similar to print without a trailing newline and limited support for escape sequences
-    Str,    // Returns a string representation of an Umpteen Value
-    Len,    // Returns the "length" of a Value (List: Entries, String: Bytes, Empty: 0, Other: 1)
-    Chr,    // Converts a number from 0 - 255 to its ASCII representation
-    Ord,    // Converts one ASCII character to its numeric representation
+    Time,    // Returns a numeric representation of the current time
+    Print,   // Print any number of values on one line to stdout, separated by spaces
+    Printx,  // Same as print, without the trailing newline
+    Str,     // Returns a string representation of an Umpteen Value
+    Len,     // Returns the "length" of a Value (Entries, Characters or Steps; Empty: 0, Other: 1)
+    Chr,     // Converts a Unicode code point to the character it stands for
+    Ord,     // Converts one character to its Unicode code point
+    GcStats, // Returns a Map of allocations, collections and live bytes as reported by the heap
+    Keys,    // Returns the keys of a Map as a List, in insertion order
+    Values,  // Returns the values of a Map as a List, in insertion order
+    Has,     // Whether a Map contains a key
+    Remove,  // Removes a key from a Map, returning its value or Empty if it was absent
+    Map,     // Returns a List of the results of calling a Fnc on each item of an iterable
+    Filter,  // Returns a List of the items of an iterable for which a Fnc returns a truthy value
+
+    // Only reachable as methods, see `NativeFnc::method`
+    Push,      // Appends a value to a List
+    Pop,       // Removes the last item of a List, returning it or Empty if the List was empty
+    Join,      // Joins the items of a List into a String, separated by another String
+    Upper,     // Returns a String in upper case
+    Lower,     // Returns a String in lower case
+    Trim,      // Returns a String without leading and trailing whitespace
+    Split,     // Splits a String into a List of the parts between each occurrence of a separator
+    Graphemes, // Splits a String into a List of the characters a reader would see, such as 'é'
+}
+
+impl NativeFnc {
+    pub const ALL: [NativeFnc; 14] = [
+        NativeFnc::Print,
+        NativeFnc::Printx,
+        NativeFnc::Time,
+        NativeFnc::Str,
+        NativeFnc::Len,
+        NativeFnc::Chr,
+        NativeFnc::Ord,
+        NativeFnc::GcStats,
+        NativeFnc::Keys,
+        NativeFnc::Values,
+        NativeFnc::Has,
+        NativeFnc::Remove,
+        NativeFnc::Map,
+        NativeFnc::Filter,
+    ];
+
+    /// Looks up the native a value of type `ty` responds to as `value.name(...)`. The value
+    /// becomes the first argument
+    pub fn method(ty: &Type, name: &str) -> Option<NativeFnc> {
+        let method = match (ty, name) {
+            (_, "str") => NativeFnc::Str,
+            (_, "len") => NativeFnc::Len,
+            (Type::List | Type::Range | Type::String | Type::Map, "map") => NativeFnc::Map,
+            (Type::List | Type::Range | Type::String | Type::Map, "filter") => NativeFnc::Filter,
+            (Type::List, "push") => NativeFnc::Push,
+            (Type::List, "pop") => NativeFnc::Pop,
+            (Type::List, "join") => NativeFnc::Join,
+            (Type::String, "upper") => NativeFnc::Upper,
+            (Type::String, "lower") => NativeFnc::Lower,
+            (Type::String, "trim") => NativeFnc::Trim,
+            (Type::String, "split") => NativeFnc::Split,
+            (Type::String, "graphemes") => NativeFnc::Graphemes,
+            (Type::Map, "keys") => NativeFnc::Keys,
+            (Type::Map, "values") => NativeFnc::Values,
+            (Type::Map, "has") => NativeFnc::Has,
+            (Type::Map, "remove") => NativeFnc::Remove,
+            _ => return None,
+        };
+
+        Some(method)
+    }
+
+    /// Borrows the List a native was given, or reports the argument's type
+    fn list_arg<'a>(&self, arg: &'a Value) -> Result<RefMut<'a, Vec<Value>>, InterpretError> {
+        let list = match arg {
+            Value::Object(obj) => RefMut::filter_map(obj.borrow_mut(), |obj| match obj {
+                Object::List(list) => Some(&mut **list),
+                _ => None,
+            })
+            .ok(),
+            _ => None,
+        };
+
+        list.ok_or_else(|| InterpretError::ArgumentTypeMismatch {
+            fnc: self.name(),
+            param: String::from("list"),
+            expected: Type::List,
+            got: Type::of(arg),
+        })
+    }
+
+    /// Reads the Number a native was given, or reports the argument's type
+    fn number_arg(&self, arg: &Value, param: &str) -> Result<f64, InterpretError> {
+        match arg {
+            Value::Number(x) => Ok(*x),
+            _ => Err(InterpretError::ArgumentTypeMismatch {
+                fnc: self.name(),
+                param: param.to_string(),
+                expected: Type::Number,
+                got: Type::of(arg),
+            }),
+        }
+    }
+
+    /// Reads the String a native was given, or reports the argument's type
+    fn string_arg<'a>(&self, arg: &'a Value, param: &str) -> Result<&'a str, InterpretError> {
+        match arg {
+            Value::String(s) => Ok(s),
+            _ => Err(InterpretError::ArgumentTypeMismatch {
+                fnc: self.name(),
+                param: param.to_string(),
+                expected: Type::String,
+                got: Type::of(arg),
+            }),
+        }
+    }
+
+    /// Borrows the Map a native was given, or reports the argument's type
+    fn map_arg<'a>(&self, arg: &'a Value) -> Result<RefMut<'a, Map>, InterpretError> {
+        let map = match arg {
+            Value::Object(obj) => RefMut::filter_map(obj.borrow_mut(), |obj| match obj {
+                Object::Map(map) => Some(map),
+                _ => None,
+            })
+            .ok(),
+            _ => None,
+        };
+
+        map.ok_or_else(|| InterpretError::ArgumentTypeMismatch {
+            fnc: self.name(),
+            param: String::from("map"),
+            expected: Type::Map,
+            got: Type::of(arg),
+        })
+    }
+
+    /// Clones out the function a native was given, so that calling it holds no borrow
+    fn fnc_arg(&self, arg: &Value) -> Result<Fnc, InterpretError> {
+        if let Value::Object(obj) = arg {
+            if let Object::Fnc(ref fnc) = *obj.borrow() {
+                return Ok(fnc.clone());
+            }
+        }
+
+        Err(InterpretError::ArgumentTypeMismatch {
+            fnc: self.name(),
+            param: String::from("fnc"),
+            expected: Type::Fnc,
+            got: Type::of(arg),
+        })
+    }
+}
+
+fn join(args: &[Value]) -> String {
+    let strings: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+    strings.join(" ")
 }
 
 impl Call for NativeFnc {
@@ -29,11 +281,11 @@ impl Call for NativeFnc {
                 Value::from(now)
             }
             NativeFnc::Print => {
-                println!("{}", args[0]);
+                println!("{}", join(args));
                 Value::Empty
             }
             NativeFnc::Printx => {
-                print!("{}", Value::from(&args[0].to_string()));
+                print!("{}", join(args));
                 Value::Empty
             }
             NativeFnc::Str => {
@@ -44,81 +296,263 @@ impl Call for NativeFnc {
                 Value::Empty => Value::from(0.0),
                 Value::Boolean(_) => Value::from(1.0),
                 Value::Number(_) => Value::from(1.0),
-                Value::String(s) => Value::from(s.len() as f64),
+                Value::String(s) => Value::from(s.chars().count() as f64),
                 Value::Object(ref obj) => match *obj.borrow() {
                     Object::List(ref list) => Value::from(list.len() as f64),
-                    Object::Fnc(_) => Value::from(1.0),
+                    Object::Fnc(_) | Object::Record(_) | Object::Enum(_) | Object::Variant(_) => {
+                        Value::from(1.0)
+                    }
+                    Object::Range(ref range) => Value::from(range.count()? as f64),
+                    Object::Map(ref map) => Value::from(map.len() as f64),
                 },
             },
-            NativeFnc::Chr => match &args[0] {
-                Value::Number(x) if (0.0..=255.0).contains(x) => {
-                    Value::from(x.trunc() as u8 as char)
-                }
-                _ => {
-                    eprintln!("Value must be a number from 0 - 255");
-                    Value::Empty
+            NativeFnc::Chr => {
+                let code = self.number_arg(&args[0], "code")?;
+                let c = match code.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&code) {
+                    true => char::from_u32(code as u32),
+                    false => None,
+                };
+                Value::from(c.ok_or(InterpretError::InvalidCodePoint(code))?)
+            }
+            NativeFnc::Ord => {
+                let s = self.string_arg(&args[0], "char")?;
+                let mut chars = s.chars();
+                let (Some(c), None) = (chars.next(), chars.next()) else {
+                    Err(InterpretError::NotACharacter(s.to_string()))?
+                };
+                Value::from(c as u32 as f64)
+            }
+            NativeFnc::GcStats => {
+                let stats = heap::stats();
+                let mut map = Map::new();
+                for (key, count) in [
+                    ("allocations", stats.allocations),
+                    ("collections", stats.collections),
+                    ("live_bytes", stats.live_bytes),
+                ] {
+                    map.insert(Key::String(key.to_string()), Value::from(count as f64));
                 }
-            },
-            NativeFnc::Ord => match &args[0] {
-                Value::String(c) => Value::from(c.as_bytes()[0] as f64),
+                Value::Object(Object::map(map))
+            }
+            NativeFnc::Keys => {
+                let keys = self.map_arg(&args[0])?.keys().map(Value::from).collect();
+                Value::Object(Object::list(keys))
+            }
+            NativeFnc::Values => {
+                let values = self.map_arg(&args[0])?.values().cloned().collect();
+                Value::Object(Object::list(values))
+            }
+            NativeFnc::Has => {
+                let key = Key::try_from(&args[1]);
+                let map = self.map_arg(&args[0])?;
+                Value::from(key.is_ok_and(|key| map.contains(&key)))
+            }
+            NativeFnc::Remove => {
+                let key = Key::try_from(&args[1])?;
+                let removed = self.map_arg(&args[0])?.remove(&key);
+                removed.unwrap_or_default()
+            }
+            NativeFnc::Map | NativeFnc::Filter => {
+                let mut fnc = self.fnc_arg(&args[1])?;
+                check_arity(&fnc, 1)?;
 
-                _ => {
-                    eprintln!("Value must be a number from 0 - 255");
-                    Value::Empty
+                let iterable = args[0].clone().iterable();
+                let mut results = vec![];
+                let mut position = 0;
+                while let Some(item) = iterable.iterate(position)? {
+                    position += 1;
+                    let result = fnc.call(vm, std::slice::from_ref(&item))?;
+                    if *self == NativeFnc::Map {
+                        results.push(result);
+                    } else if result.truthy() {
+                        results.push(item);
+                    }
                 }
-            },
+                Value::Object(Object::list(results))
+            }
+            NativeFnc::Push => {
+                // Read first, since the value may be the List itself
+                let value = args[1].clone();
+                self.list_arg(&args[0])?.push(value);
+                Value::Empty
+            }
+            NativeFnc::Pop => self.list_arg(&args[0])?.pop().unwrap_or_default(),
+            NativeFnc::Join => {
+                let separator = self.string_arg(&args[1], "separator")?.to_string();
+                let items = self.list_arg(&args[0])?.clone();
+                let strings: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+                Value::String(Box::new(strings.join(&separator)))
+            }
+            NativeFnc::Upper | NativeFnc::Lower | NativeFnc::Trim => {
+                let s = self.string_arg(&args[0], "string")?;
+                let result = match self {
+                    NativeFnc::Upper => s.to_uppercase(),
+                    NativeFnc::Lower => s.to_lowercase(),
+                    _ => s.trim().to_string(),
+                };
+                Value::String(Box::new(result))
+            }
+            NativeFnc::Split => {
+                let s = self.string_arg(&args[0], "string")?;
+                let separator = self.string_arg(&args[1], "separator")?;
+                let parts = s
+                    .split(separator)
+                    .map(|part| Value::String(Box::new(part.to_string())))
+                    .collect();
+                Value::Object(Object::list(parts))
+            }
+            NativeFnc::Graphemes => {
+                let s = self.string_arg(&args[0], "string")?;
+                let graphemes = s
+                    .graphemes(true)
+                    .map(|g| Value::String(Box::new(g.to_string())))
+                    .collect();
+                Value::Object(Object::list(graphemes))
+            }
         };
 
         Ok(return_value)
     }
 
-    fn arity(&self) -> usize {
+    fn arity(&self) -> Arity {
         match self {
-            NativeFnc::Time => 0,
+            NativeFnc::Time | NativeFnc::GcStats => Arity::fixed(0),
+            NativeFnc::Print | NativeFnc::Printx => Arity::variadic(0),
+            NativeFnc::Has
+            | NativeFnc::Remove
+            | NativeFnc::Map
+            | NativeFnc::Filter
+            | NativeFnc::Push
+            | NativeFnc::Join
+            | NativeFnc::Split => Arity::fixed(2),
 
-            _ => 1,
+            _ => Arity::fixed(1),
         }
     }
 
     fn name(&self) -> String {
-        format!("{:?}", self).to_ascii_lowercase()
+        match self {
+            NativeFnc::GcStats => String::from("gc_stats"),
+            _ => format!("{:?}", self).to_ascii_lowercase(),
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct UserFnc {
     name: String,
-    arity: usize,
-    params: Vec<(String, String)>,
-    body: Vec<Stmt>,
+    params: Vec<Param>,
+    returns: Type,
+    body: Rc<Vec<Stmt>>, // Shared, since calling a function clones it out of its object
+    closure: Option<Rc<Scope>>, // The scope the function was defined in, or None for globals
 }
 
 impl UserFnc {
-    pub fn new(name: String, params: Vec<(String, String)>, body: Vec<Stmt>) -> Self {
+    pub fn new(
+        name: String,
+        params: Vec<Param>,
+        returns: Type,
+        body: Vec<Stmt>,
+        closure: Option<Rc<Scope>>,
+    ) -> Self {
         UserFnc {
             name,
-            arity: params.len(),
             params,
-            body,
+            returns,
+            body: Rc::new(body),
+            closure,
         }
     }
+
+    pub fn closure(&self) -> Option<&Rc<Scope>> {
+        self.closure.as_ref()
+    }
+
+    /// Lets go of the defining scope, used by the collector to break cycles
+    pub(crate) fn release(&mut self) {
+        self.closure = None;
+    }
+
+    fn run(
+        &self,
+        vm: &mut Interpreter,
+        args: &[Value],
+    ) -> Result<Option<Divergence>, UmpteenError> {
+        let scope = vm.new_context(self.closure.clone());
+        self.bind_params(vm, &scope, args)?;
+        vm.exec_block(&self.body, Some(scope))
+    }
+
+    fn bind_params(
+        &self,
+        vm: &mut Interpreter,
+        scope: &Rc<Scope>,
+        args: &[Value],
+    ) -> Result<(), UmpteenError> {
+        for (i, param) in self.params.iter().enumerate() {
+            // Defaults see the parameters before them, so they are evaluated in the call scope
+            let value = match (args.get(i), &param.default) {
+                (Some(arg), _) => arg.clone(),
+                (None, Some(default)) => {
+                    let value = vm.eval_in(default, scope)?;
+                    check_args(
+                        &self.name,
+                        [(&param.name, &param.ty)],
+                        std::slice::from_ref(&value),
+                    )?;
+                    value
+                }
+                (None, None) => unreachable!(),
+            };
+            vm.bind(scope, &param.name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PartialEq for UserFnc {
+    fn eq(&self, other: &Self) -> bool {
+        let same_closure = match (&self.closure, &other.closure) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+
+        self.name == other.name
+            && self.params == other.params
+            && self.returns == other.returns
+            && self.body == other.body
+            && same_closure
+    }
 }
 
 impl Call for UserFnc {
     fn call(&mut self, vm: &mut Interpreter, args: &[Value]) -> Result<Value, UmpteenError> {
-        let (mem_key, mem) = vm.new_context();
-        for (i, (param, _)) in self.params.iter().enumerate() {
-            mem.declare(param).unwrap();
-            mem.assign(param, None, args[i].clone())?;
-        }
+        check_arity(self, args.len())?;
+        check_args(
+            &self.name,
+            self.params.iter().map(|p| (&p.name, &p.ty)),
+            args,
+        )?;
+
+        vm.enter()?;
+        let divergence = self.run(vm, args);
+        vm.leave(1);
 
-        vm.exec_block(&self.body, Some(mem_key))?;
+        let value = match divergence? {
+            Some(Divergence::Return(value)) => value,
+            Some(divergence) => Err(InterpretError::from(divergence))?,
+            None => Value::Empty,
+        };
+        check_return(&self.name, &self.returns, &value)?;
 
-        Ok(Value::Empty)
+        Ok(value)
     }
 
-    fn arity(&self) -> usize {
-        self.arity
+    fn arity(&self) -> Arity {
+        let required = self.params.iter().filter(|p| p.default.is_none()).count();
+        Arity::range(required, self.params.len())
     }
 
     fn name(&self) -> String {
@@ -126,10 +560,76 @@ impl Call for UserFnc {
     }
 }
 
+/// A compiled function paired with the frame it was created in
+#[derive(Debug, Clone)]
+pub struct Closure {
+    proto: Rc<Prototype>,
+    env: Option<Rc<Frame>>,
+}
+
+impl Closure {
+    pub fn new(proto: Prototype) -> Self {
+        Closure {
+            proto: Rc::new(proto),
+            env: None,
+        }
+    }
+
+    pub fn capture(&self, env: Rc<Frame>) -> Self {
+        Closure {
+            proto: self.proto.clone(),
+            env: Some(env),
+        }
+    }
+
+    pub fn proto(&self) -> &Rc<Prototype> {
+        &self.proto
+    }
+
+    pub fn env(&self) -> Option<&Rc<Frame>> {
+        self.env.as_ref()
+    }
+
+    /// Lets go of the captured frame, used by the collector to break cycles
+    pub(crate) fn release(&mut self) {
+        self.env = None;
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        let same_env = match (&self.env, &other.env) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+
+        Rc::ptr_eq(&self.proto, &other.proto) && same_env
+    }
+}
+
+impl Call for Closure {
+    fn call(&mut self, vm: &mut Interpreter, args: &[Value]) -> Result<Value, UmpteenError> {
+        Vm::new().run(vm, self.clone(), args)
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::range(self.proto.required, self.proto.params.len())
+    }
+
+    fn name(&self) -> String {
+        self.proto.name.clone()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Fnc {
     Native(NativeFnc),
     User(UserFnc),
+    Compiled(Closure),
+    Record(Rc<RecordType>), // Constructs an instance from one argument per field
+    Variant(Rc<EnumType>, usize), // Constructs the variant with this tag from its payload
+    Bound(Value, Box<Fnc>), // A method paired with the value it was called on
 }
 
 impl Call for Fnc {
@@ -137,13 +637,36 @@ impl Call for Fnc {
         match self {
             Fnc::Native(f) => f.call(vm, args),
             Fnc::User(f) => f.call(vm, args),
+            Fnc::Compiled(f) => f.call(vm, args),
+            Fnc::Record(ty) => {
+                for (field, arg) in ty.fields.iter().zip(args) {
+                    ty.check(&field.name, arg)?;
+                }
+                let record = Record::new(ty.clone(), args.to_vec());
+                Ok(Value::Object(Object::record(record)))
+            }
+            Fnc::Variant(ty, tag) => Ok(ty.construct(*tag, args)?),
+            Fnc::Bound(receiver, method) => {
+                let args = [std::slice::from_ref(receiver), args].concat();
+                method.call(vm, &args)
+            }
         }
     }
 
-    fn arity(&self) -> usize {
+    fn arity(&self) -> Arity {
         match self {
             Fnc::Native(n) => n.arity(),
             Fnc::User(u) => u.arity(),
+            Fnc::Compiled(c) => c.arity(),
+            Fnc::Record(ty) => Arity::fixed(ty.fields.len()),
+            Fnc::Variant(ty, tag) => Arity::fixed(ty.variants[*tag].fields.len()),
+            Fnc::Bound(_, method) => {
+                let Arity { min, max } = method.arity();
+                Arity {
+                    min: min.saturating_sub(1),
+                    max: max.map(|max| max.saturating_sub(1)),
+                }
+            }
         }
     }
 
@@ -151,6 +674,10 @@ impl Call for Fnc {
         match self {
             Fnc::Native(n) => n.name(),
             Fnc::User(u) => u.name(),
+            Fnc::Compiled(c) => c.name(),
+            Fnc::Record(ty) => ty.name.clone(),
+            Fnc::Variant(ty, tag) => format!("{}.{}", ty.name, ty.variants[*tag].name),
+            Fnc::Bound(_, method) => method.name(),
         }
     }
 }
@@ -160,6 +687,10 @@ impl Display for Fnc {
         match self {
             Fnc::Native(nf) => write!(f, "<native fnc {}()>", nf.name()),
             Fnc::User(uf) => write!(f, "<fnc {}()>", uf.name()),
+            Fnc::Compiled(cf) => write!(f, "<fnc {}()>", cf.name()),
+            Fnc::Record(ty) => write!(f, "<type {}>", ty.name),
+            Fnc::Variant(..) => write!(f, "<variant {}>", self.name()),
+            Fnc::Bound(..) => write!(f, "<method {}()>", self.name()),
         }
     }
 }
@@ -0,0 +1,156 @@
+use std::fmt::Display;
+
+use crate::error::ParseError;
+
+use super::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '<' => Some(Align::Left),
+            '^' => Some(Align::Center),
+            '>' => Some(Align::Right),
+            _ => None,
+        }
+    }
+}
+
+/// How an interpolated value is written, given after a colon as in `{x:>8.2}`. The spec is
+/// `[[fill]align][0][width][.precision]`; precision counts decimal places for Numbers and
+/// truncates anything else to that many characters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatSpec {
+    pub fill: char,
+    pub align: Option<Align>,
+    pub zero: bool, // Pads Numbers with zeroes after their sign
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+}
+
+impl Default for FormatSpec {
+    fn default() -> Self {
+        FormatSpec {
+            fill: ' ',
+            align: None,
+            zero: false,
+            width: None,
+            precision: None,
+        }
+    }
+}
+
+impl FormatSpec {
+    pub fn parse(spec: &str) -> Result<Self, ParseError> {
+        let invalid = || ParseError::InvalidFormatSpec(spec.to_string());
+        let chars: Vec<char> = spec.chars().collect();
+        let mut format = FormatSpec::default();
+        let mut i = 0;
+
+        if let Some(align) = chars.get(1).and_then(|&c| Align::from_char(c)) {
+            format.fill = chars[0];
+            format.align = Some(align);
+            i = 2;
+        } else if let Some(align) = chars.first().and_then(|&c| Align::from_char(c)) {
+            format.align = Some(align);
+            i = 1;
+        }
+
+        if chars.get(i) == Some(&'0') {
+            format.zero = true;
+            i += 1;
+        }
+
+        let digits = |i: &mut usize| {
+            let start = *i;
+            while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+                *i += 1;
+            }
+            match *i > start {
+                true => chars[start..*i]
+                    .iter()
+                    .collect::<String>()
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| invalid()),
+                false => Ok(None),
+            }
+        };
+
+        format.width = digits(&mut i)?;
+        if chars.get(i) == Some(&'.') {
+            i += 1;
+            format.precision = Some(digits(&mut i)?.ok_or_else(invalid)?);
+        }
+
+        if i != chars.len() {
+            Err(invalid())?
+        }
+
+        Ok(format)
+    }
+
+    pub fn apply(&self, value: &Value) -> String {
+        let number = matches!(value, Value::Number(_));
+        let text = match (value, self.precision) {
+            (Value::Number(x), Some(places)) => format!("{:.*}", places, x),
+            (_, Some(len)) => value.to_string().chars().take(len).collect(),
+            (_, None) => value.to_string(),
+        };
+
+        let len = text.chars().count();
+        let pad = match self.width {
+            Some(width) if width > len => width - len,
+            _ => return text,
+        };
+
+        if self.zero && number {
+            let (sign, digits) = match text.strip_prefix('-') {
+                Some(digits) => ("-", digits),
+                None => ("", text.as_str()),
+            };
+            return format!("{}{}{}", sign, "0".repeat(pad), digits);
+        }
+
+        let fill = |n: usize| self.fill.to_string().repeat(n);
+        let align = match self.align {
+            Some(align) => align,
+            None if number => Align::Right,
+            None => Align::Left,
+        };
+        match align {
+            Align::Left => format!("{}{}", text, fill(pad)),
+            Align::Center => format!("{}{}{}", fill(pad / 2), text, fill(pad - pad / 2)),
+            Align::Right => format!("{}{}", fill(pad), text),
+        }
+    }
+}
+
+impl Display for FormatSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(align) = self.align {
+            let c = match align {
+                Align::Left => '<',
+                Align::Center => '^',
+                Align::Right => '>',
+            };
+            write!(f, "{}{}", self.fill, c)?;
+        }
+        if self.zero {
+            write!(f, "0")?;
+        }
+        if let Some(width) = self.width {
+            write!(f, "{}", width)?;
+        }
+        if let Some(precision) = self.precision {
+            write!(f, ".{}", precision)?;
+        }
+        Ok(())
+    }
+}
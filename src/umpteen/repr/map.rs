@@ -0,0 +1,154 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::error::MemoryError;
+
+use super::value::Value;
+
+/// The hashable subset of `Value`s that may be used as map keys
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Empty,
+    Boolean(bool),
+    Number(u64), // Bits of the number, with -0 folded into 0
+    String(String),
+}
+
+impl TryFrom<&Value> for Key {
+    type Error = MemoryError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        let key = match value {
+            Value::Empty => Key::Empty,
+            Value::Boolean(x) => Key::Boolean(*x),
+            Value::Number(x) if !x.is_nan() => Key::Number((x + 0.0).to_bits()),
+            Value::String(s) => Key::String(s.to_string()),
+            _ => Err(MemoryError::UnhashableKey(value.to_string()))?,
+        };
+
+        Ok(key)
+    }
+}
+
+impl From<&Key> for Value {
+    fn from(key: &Key) -> Self {
+        match key {
+            Key::Empty => Value::Empty,
+            Key::Boolean(x) => Value::Boolean(*x),
+            Key::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            Key::String(s) => Value::from(s),
+        }
+    }
+}
+
+/// Key-value pairs kept in the order their keys were first inserted
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(Key, Value)>,
+    positions: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.positions.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Replaces the value of an existing key in place, or appends a new entry
+    pub fn insert(&mut self, key: Key, value: Value) {
+        match self.positions.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let i = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.positions.get_mut(key).unwrap() -= 1;
+        }
+
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    /// The key of the entry at `position` in insertion order
+    pub fn key_at(&self, position: usize) -> Option<&Key> {
+        self.entries.get(position).map(|(key, _)| key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .entries
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl Display for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(key, value)| format!("{}: {}", Value::from(key), value))
+            .collect();
+        write!(f, "{{{}}}", entries.join(", "))
+    }
+}
+
+#[test]
+fn maps_keep_insertion_order() {
+    let key = |s: &str| Key::try_from(&Value::from(s)).unwrap();
+
+    let mut map = Map::new();
+    map.insert(key("b"), Value::from(1.0));
+    map.insert(key("a"), Value::from(2.0));
+    map.insert(key("c"), Value::from(3.0));
+    map.insert(key("b"), Value::from(4.0));
+    assert_eq!(map.to_string(), "{b: 4, a: 2, c: 3}");
+
+    assert_eq!(map.remove(&key("a")), Some(Value::from(2.0)));
+    assert_eq!(map.get(&key("c")), Some(&Value::from(3.0)));
+    assert_eq!(map.to_string(), "{b: 4, c: 3}");
+    assert_eq!(
+        Key::try_from(&Value::from(-0.0)).unwrap(),
+        Key::try_from(&Value::from(0.0)).unwrap()
+    );
+}
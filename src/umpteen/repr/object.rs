@@ -7,8 +7,14 @@ use std::{
     rc::Rc,
 };
 
+use crate::exec::heap;
+
 use super::{
-    fnc::{Fnc, NativeFnc, UserFnc},
+    enumeration::{EnumType, Instance},
+    fnc::{Closure, Fnc, NativeFnc, UserFnc},
+    map::Map,
+    range::Range,
+    record::{Record, RecordType},
     value::Value,
 };
 
@@ -39,26 +45,51 @@ impl DerefMut for List {
 pub enum Object {
     List(List),
     Fnc(Fnc),
+    Range(Range),
+    Map(Map),
+    Record(Record),
+    Enum(Rc<EnumType>),
+    Variant(Instance),
 }
 
 impl Object {
     pub fn is_empty(&self) -> bool {
         match self {
             Object::List(values) => values.is_empty(),
-            Object::Fnc(_) => false,
+            Object::Fnc(_) | Object::Record(_) | Object::Enum(_) | Object::Variant(_) => false,
+            Object::Range(range) => range.is_empty(),
+            Object::Map(map) => map.is_empty(),
         }
     }
 
-    pub fn list(values: Vec<Value>) -> RefCell<Self> {
+    pub fn list(values: Vec<Value>) -> Rc<RefCell<Self>> {
         Self::create(Object::List(List(values)))
     }
 
-    fn fnc(f: Fnc) -> RefCell<Self> {
+    pub fn map(map: Map) -> Rc<RefCell<Self>> {
+        Self::create(Object::Map(map))
+    }
+
+    pub fn record(record: Record) -> Rc<RefCell<Self>> {
+        Self::create(Object::Record(record))
+    }
+
+    pub fn variant(instance: Instance) -> Rc<RefCell<Self>> {
+        Self::create(Object::Variant(instance))
+    }
+
+    pub fn range(range: Range) -> Rc<RefCell<Self>> {
+        Self::create(Object::Range(range))
+    }
+
+    pub fn fnc(f: Fnc) -> Rc<RefCell<Self>> {
         Self::create(Object::Fnc(f))
     }
 
-    fn create(obj: Object) -> RefCell<Self> {
-        RefCell::new(obj)
+    fn create(obj: Object) -> Rc<RefCell<Self>> {
+        let obj = Rc::new(RefCell::new(obj));
+        heap::track_object(&obj);
+        obj
     }
 }
 
@@ -81,6 +112,11 @@ impl Display for Object {
                 write!(f, "{}", buffer)
             }
             Object::Fnc(fnc) => write!(f, "{}", fnc),
+            Object::Range(range) => write!(f, "{}", range),
+            Object::Map(map) => write!(f, "{}", map),
+            Object::Record(record) => write!(f, "{}", record),
+            Object::Enum(ty) => write!(f, "<enum {}>", ty.name),
+            Object::Variant(instance) => write!(f, "{}", instance),
         }
     }
 }
@@ -90,6 +126,21 @@ impl From<UserFnc> for Value {
         Value::Object(Object::fnc(Fnc::User(value)))
     }
 }
+impl From<Closure> for Value {
+    fn from(value: Closure) -> Self {
+        Value::Object(Object::fnc(Fnc::Compiled(value)))
+    }
+}
+impl From<RecordType> for Value {
+    fn from(value: RecordType) -> Self {
+        Value::Object(Object::fnc(Fnc::Record(Rc::new(value))))
+    }
+}
+impl From<EnumType> for Value {
+    fn from(value: EnumType) -> Self {
+        Value::Object(Object::create(Object::Enum(Rc::new(value))))
+    }
+}
 impl From<NativeFnc> for Value {
     fn from(value: NativeFnc) -> Self {
         Value::Object(Object::fnc(Fnc::Native(value)))
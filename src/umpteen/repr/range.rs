@@ -0,0 +1,148 @@
+use std::fmt::Display;
+
+use crate::error::InterpretError;
+
+use super::{types::Type, value::Value};
+
+// Beyond this many steps, neighbouring items of a range can't be told apart as Numbers
+const MAX_STEPS: f64 = 9007199254740992.0;
+
+/// Numbers counting from `start` towards `end` in increments of `step`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    start: f64,
+    end: f64,
+    step: f64,
+    inclusive: bool,
+}
+
+impl Range {
+    pub fn new(
+        start: &Value,
+        end: &Value,
+        step: &Value,
+        inclusive: bool,
+    ) -> Result<Self, InterpretError> {
+        let bound = |value: &Value| match value {
+            Value::Number(x) if x.is_finite() => Ok(*x),
+            _ => Err(InterpretError::InvalidRange(format!(
+                "bounds must be finite numbers, got {}",
+                Type::of(value)
+            ))),
+        };
+
+        let step = match step {
+            Value::Empty => 1.0,
+            Value::Number(x) if x.is_finite() && *x != 0.0 => *x,
+            _ => Err(InterpretError::InvalidRange(format!(
+                "step must be a non-zero number, got {}",
+                step
+            )))?,
+        };
+
+        Ok(Range {
+            start: bound(start)?,
+            end: bound(end)?,
+            step,
+            inclusive,
+        })
+    }
+
+    /// The number of items, or an error when there are too many to count exactly
+    pub fn count(&self) -> Result<usize, InterpretError> {
+        match (self.end - self.start) / self.step > MAX_STEPS {
+            true => Err(InterpretError::InvalidRange(format!(
+                "takes more than {} steps",
+                MAX_STEPS
+            ))),
+            false => Ok(self.len()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let span = (self.end - self.start) / self.step;
+        if span < 0.0 {
+            0
+        } else if self.inclusive {
+            span.floor() as usize + 1
+        } else {
+            span.ceil() as usize
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn nth(&self, n: usize) -> Option<f64> {
+        (n < self.len()).then_some(self.start + n as f64 * self.step)
+    }
+
+    pub fn contains(&self, x: f64) -> bool {
+        let n = (x - self.start) / self.step;
+        n >= 0.0 && n.fract() == 0.0 && (n as usize) < self.len()
+    }
+
+    /// The start, end and step, followed by whether the end is included
+    pub fn parts(&self) -> (f64, f64, f64, bool) {
+        (self.start, self.end, self.step, self.inclusive)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.len()).map(|n| self.start + n as f64 * self.step)
+    }
+
+    /// Iterates over the items from `low` to `high`, without visiting the steps outside them.
+    /// The window is widened by a step either way to allow for rounding, so callers still filter
+    pub fn between(&self, low: f64, high: f64) -> impl Iterator<Item = f64> + '_ {
+        let (a, b) = (
+            (low - self.start) / self.step,
+            (high - self.start) / self.step,
+        );
+        let first = (a.min(b).ceil() - 1.0).max(0.0) as usize;
+        let last = (a.max(b).floor() + 2.0).max(0.0) as usize;
+        (first..last.min(self.len())).map(|n| self.start + n as f64 * self.step)
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dots = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, dots, self.end)?;
+        if self.step != 1.0 {
+            write!(f, " by {}", self.step)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn ranges_count_towards_their_end() {
+    let range = |start: f64, end: f64, step: f64, inclusive| {
+        let step = Value::from(step);
+        Range::new(&Value::from(start), &Value::from(end), &step, inclusive).unwrap()
+    };
+
+    assert_eq!(range(0.0, 5.0, 1.0, false).len(), 5);
+    assert_eq!(range(0.0, 5.0, 1.0, true).len(), 6);
+    assert_eq!(
+        range(0.0, 10.0, 3.0, false).iter().collect::<Vec<_>>(),
+        [0.0, 3.0, 6.0, 9.0]
+    );
+    assert_eq!(range(5.0, 0.0, -2.0, true).nth(2), Some(1.0));
+    assert!(range(5.0, 0.0, 1.0, false).is_empty());
+    assert!(range(0.0, 10.0, 2.0, false).contains(4.0));
+    assert!(!range(0.0, 10.0, 2.0, false).contains(5.0));
+    assert!(!range(0.0, 10.0, 2.0, false).contains(10.0));
+    assert_eq!(
+        range(-5.0, 1e15, 1.0, false)
+            .between(0.0, 2.0)
+            .collect::<Vec<_>>(),
+        [-1.0, 0.0, 1.0, 2.0, 3.0]
+    );
+    let (zero, one) = (Value::from(0.0), Value::from(1.0));
+    assert!(Range::new(&zero, &one, &zero, false).is_err());
+    assert_eq!(range(0.0, 1e15, 1.0, false).count().unwrap(), 1e15 as usize);
+    assert!(range(0.0, 1e300, 1.0, false).count().is_err());
+}
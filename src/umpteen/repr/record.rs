@@ -0,0 +1,93 @@
+use std::{fmt::Display, rc::Rc};
+
+use crate::error::InterpretError;
+
+use super::{ast::stmt::Field, fnc::Fnc, types::Type, value::Value};
+
+/// A record type declared with `type`, whose name also serves as its constructor
+#[derive(Debug, PartialEq)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<Field>,
+    pub methods: Vec<(String, Fnc)>, // Each takes the instance it is called on as `self`
+}
+
+impl RecordType {
+    pub fn method(&self, name: &str) -> Option<&Fnc> {
+        self.methods
+            .iter()
+            .find_map(|(method, fnc)| (method == name).then_some(fnc))
+    }
+
+    pub fn slot(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f.name == field)
+    }
+
+    /// Checks that `value` may be stored in `field`, returning the slot it belongs in
+    pub fn check(&self, field: &str, value: &Value) -> Result<usize, InterpretError> {
+        let Some(slot) = self.slot(field) else {
+            Err(InterpretError::NoSuchField {
+                ty: Type::Named(self.name.clone()),
+                field: field.to_string(),
+            })?
+        };
+
+        let expected = &self.fields[slot].ty;
+        if !expected.admits(value) {
+            Err(InterpretError::FieldTypeMismatch {
+                record: self.name.clone(),
+                field: field.to_string(),
+                expected: expected.clone(),
+                got: Type::of(value),
+            })?
+        }
+
+        Ok(slot)
+    }
+}
+
+/// An instance of a record type, holding one value per field in declaration order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    ty: Rc<RecordType>,
+    values: Vec<Value>,
+}
+
+impl Record {
+    pub fn new(ty: Rc<RecordType>, values: Vec<Value>) -> Self {
+        Record { ty, values }
+    }
+
+    pub fn ty(&self) -> &Rc<RecordType> {
+        &self.ty
+    }
+
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        self.ty.slot(field).map(|slot| &self.values[slot])
+    }
+
+    pub fn store(&mut self, slot: usize, value: Value) {
+        self.values[slot] = value;
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<_> = self
+            .ty
+            .fields
+            .iter()
+            .zip(&self.values)
+            .map(|(field, value)| format!("{}: {}", field.name, value))
+            .collect();
+        write!(f, "{} {{ {} }}", self.ty.name, fields.join(", "))
+    }
+}
@@ -1,4 +1,7 @@
-use std::fmt::{Display, Write};
+use std::{
+    borrow::Cow,
+    fmt::{Display, Write},
+};
 
 use crate::error::Line;
 
@@ -13,6 +16,9 @@ pub enum TokenType {
     Semicolon,
     Colon,
     Comma,
+    Dot,
+    DotDot,
+    DotDotEqual,
 
     Equal,
     EqualEqual,
@@ -38,7 +44,6 @@ pub enum TokenType {
     Or,
 
     ThinArrow,
-    TypeName,
     FatArrow,
 
     Var,
@@ -46,16 +51,28 @@ pub enum TokenType {
     If,
     Else,
     Loop,
+    While,
+    For,
+    In,
+    By,
     Break,
     Continue,
     Fnc,
     Return,
+    Type,
+    Enum,
+    Match,
+    Is,
 
     True,
     False,
     Empty,
     Number,
     String,
+    StringStart,
+    StringMiddle,
+    StringEnd,
+    FormatSpec,
     Identifier,
 
     Eof,
@@ -67,16 +84,29 @@ impl Display for TokenType {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Byte offsets of a token within its source, from `start` up to but not including `end`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
 pub struct Token<'t> {
     pub kind: TokenType,
-    pub lexeme: &'t str,
-    pub line: Line,
+    pub lexeme: Cow<'t, str>, // Owned for string literals, whose escapes are already decoded
+    pub span: Span,
+    pub line: Line, // Line and column of the token's first character
 }
 
 impl<'t> Token<'t> {
-    pub fn new(kind: TokenType, lexeme: &'t str, line: Line) -> Self {
-        Self { kind, lexeme, line }
+    pub fn new(kind: TokenType, lexeme: impl Into<Cow<'t, str>>, span: Span, line: Line) -> Self {
+        Self {
+            kind,
+            lexeme: lexeme.into(),
+            span,
+            line,
+        }
     }
 
     pub fn lexeme(&self) -> String {
@@ -0,0 +1,75 @@
+use std::fmt::Display;
+
+use crate::error::ParseError;
+
+use super::{object::Object, value::Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Empty,
+    Boolean,
+    Number,
+    String,
+    List,
+    Fnc,
+    Range,
+    Map,
+    Enum,
+    Any,           // Admits every value, for parameters and returns left unannotated by lambdas
+    Named(String), // A declared record or enum type
+}
+
+impl Type {
+    pub fn of(value: &Value) -> Type {
+        match value {
+            Value::Empty => Type::Empty,
+            Value::Boolean(_) => Type::Boolean,
+            Value::Number(_) => Type::Number,
+            Value::String(_) => Type::String,
+            Value::Object(obj) => match &*obj.borrow() {
+                Object::List(_) => Type::List,
+                Object::Fnc(_) => Type::Fnc,
+                Object::Range(_) => Type::Range,
+                Object::Map(_) => Type::Map,
+                Object::Record(record) => Type::Named(record.ty().name.clone()),
+                Object::Enum(_) => Type::Enum,
+                Object::Variant(instance) => Type::Named(instance.ty().name.clone()),
+            },
+        }
+    }
+
+    pub fn admits(&self, value: &Value) -> bool {
+        *self == Type::Any || *self == Type::of(value)
+    }
+}
+
+impl TryFrom<&str> for Type {
+    type Error = ParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let ty = match value {
+            "Empty" => Type::Empty,
+            "Boolean" => Type::Boolean,
+            "Number" => Type::Number,
+            "String" => Type::String,
+            "List" => Type::List,
+            "Fnc" => Type::Fnc,
+            "Range" => Type::Range,
+            "Map" => Type::Map,
+            "Enum" => Type::Enum,
+            "Any" => Type::Any,
+            _ => Err(ParseError::UnknownType(value.to_string()))?,
+        };
+
+        Ok(ty)
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Named(name) => write!(f, "{}", name),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
@@ -3,23 +3,31 @@ use std::{
     fmt::Display,
     ops::{Add, Div, Mul, Neg, Not, Rem, Sub},
     process::{ExitCode, Termination},
+    rc::Rc,
 };
 
-use crate::{boxed, error::ParseError, umpteen::util::unescape};
+use crate::{
+    boxed,
+    error::{InterpretError, MemoryError, ParseError},
+};
 
 use super::{
     ast::ops::{Binary, Unary},
+    fnc::{Fnc, NativeFnc},
+    map::Key,
     object::Object,
+    range::Range,
+    types::Type,
 };
 
-#[derive(Default, Debug, Clone, PartialEq)]
+#[derive(Default, Debug, Clone)]
 pub enum Value {
     #[default]
     Empty,
     Boolean(bool),
     Number(f64),
     String(Box<String>),
-    Object(RefCell<Object>),
+    Object(Rc<RefCell<Object>>),
 }
 
 impl Value {
@@ -43,6 +51,379 @@ impl Value {
             Value::Object(x) => !x.borrow().is_empty(),
         }
     }
+
+    /// Identity comparison: objects must be the same instance, anything else compares by value
+    pub fn is(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+            _ => self == other,
+        }
+    }
+
+    /// Reads `self[idx]`. Lists and Strings take a Number or a Range, Maps take any key
+    pub fn index(&self, idx: &Value) -> Result<Value, MemoryError> {
+        if let Some(range) = idx.range() {
+            return self.slice(&range);
+        }
+
+        let number = || match idx {
+            Value::Number(x) => Ok(*x),
+            _ => Err(MemoryError::CannotIndexWith(self.to_string())),
+        };
+
+        if let Value::String(s) = self {
+            let idx = position(number()?, s.chars().count())?;
+            return Ok(s.chars().nth(idx).map(Value::from).unwrap_or_default());
+        }
+
+        if let Value::Object(obj) = self {
+            match *obj.borrow() {
+                Object::List(ref list) => {
+                    return Ok(list[position(number()?, list.len())?].clone());
+                }
+                Object::Map(ref map) => {
+                    let value = map.get(&Key::try_from(idx)?);
+                    return value
+                        .cloned()
+                        .ok_or_else(|| MemoryError::NoSuchKey(idx.to_string()));
+                }
+                _ => (),
+            }
+        }
+
+        Err(MemoryError::CannotIndex(self.to_string()))
+    }
+
+    /// What a `for` loop walks over in place of this value. A String is split into its
+    /// characters up front, so that each step of the loop takes the same time
+    pub fn iterable(self) -> Value {
+        match self {
+            Value::String(s) => Value::Object(Object::list(s.chars().map(Value::from).collect())),
+            value => value,
+        }
+    }
+
+    /// The item a `for` loop visits at `position`, or None once it has run out. Strings must be
+    /// made `iterable` first
+    pub fn iterate(&self, position: usize) -> Result<Option<Value>, InterpretError> {
+        if let Value::Object(obj) = self {
+            match *obj.borrow() {
+                Object::List(ref list) => return Ok(list.get(position).cloned()),
+                Object::Range(ref range) => return Ok(range.nth(position).map(Value::from)),
+                Object::Map(ref map) => return Ok(map.key_at(position).map(Value::from)),
+                Object::Fnc(_) | Object::Record(_) | Object::Enum(_) | Object::Variant(_) => (),
+            }
+        }
+
+        Err(InterpretError::NotIterable(Type::of(self)))
+    }
+
+    /// Reads a field or variant, falling back to a method bound to this value
+    pub fn get_field(&self, field: &str) -> Result<Value, InterpretError> {
+        if let Value::Object(obj) = self {
+            match *obj.borrow() {
+                Object::Record(ref record) => {
+                    if let Some(value) = record.get(field) {
+                        return Ok(value.clone());
+                    }
+                    if let Some(method) = record.ty().method(field) {
+                        let bound = Fnc::Bound(self.clone(), Box::new(method.clone()));
+                        return Ok(Value::Object(Object::fnc(bound)));
+                    }
+                }
+                Object::Enum(ref ty) => {
+                    let Some(tag) = ty.tag(field) else {
+                        Err(InterpretError::NoSuchVariant {
+                            ty: ty.name.clone(),
+                            variant: field.to_string(),
+                        })?
+                    };
+
+                    // Unit variants are values in their own right, the rest are constructors
+                    return match ty.variants[tag].fields.is_empty() {
+                        true => ty.construct(tag, &[]),
+                        false => Ok(Value::Object(Object::fnc(Fnc::Variant(ty.clone(), tag)))),
+                    };
+                }
+                _ => (),
+            }
+        }
+
+        match NativeFnc::method(&Type::of(self), field) {
+            Some(method) => {
+                let bound = Fnc::Bound(self.clone(), Box::new(Fnc::Native(method)));
+                Ok(Value::Object(Object::fnc(bound)))
+            }
+            None => Err(InterpretError::NoSuchField {
+                ty: Type::of(self),
+                field: field.to_string(),
+            }),
+        }
+    }
+
+    pub fn set_field(&self, field: &str, value: Value) -> Result<(), InterpretError> {
+        if let Value::Object(obj) = self {
+            // Checked before borrowing mutably, since `value` may be this very record
+            let slot = match *obj.borrow() {
+                Object::Record(ref record) => Some(record.ty().check(field, &value)?),
+                _ => None,
+            };
+            if let (Some(slot), Object::Record(record)) = (slot, &mut *obj.borrow_mut()) {
+                record.store(slot, value);
+                return Ok(());
+            }
+        }
+
+        Err(InterpretError::NoSuchField {
+            ty: Type::of(self),
+            field: field.to_string(),
+        })
+    }
+
+    pub fn range(&self) -> Option<Range> {
+        let Value::Object(obj) = self else {
+            return None;
+        };
+
+        match *obj.borrow() {
+            Object::Range(range) => Some(range),
+            _ => None,
+        }
+    }
+
+    /// The items at each position in `range` that falls inside this List or String
+    pub fn slice(&self, range: &Range) -> Result<Value, MemoryError> {
+        let len = self
+            .indexable_len()
+            .ok_or_else(|| MemoryError::CannotIndex(self.to_string()))?;
+        let positions = range
+            .between(0.0, len as f64 - 1.0)
+            .filter(|x| *x >= 0.0 && x.fract() == 0.0)
+            .map(|x| x as usize);
+
+        self.pick(positions)
+    }
+
+    /// Reads `self[start:end:step]`, where any of the three may be Empty to take its default
+    pub fn slice_by(&self, start: &Value, end: &Value, step: &Value) -> Result<Value, MemoryError> {
+        let len = self
+            .indexable_len()
+            .ok_or_else(|| MemoryError::CannotIndex(self.to_string()))? as i64;
+        let part = |value: &Value| match value {
+            Value::Empty => Ok(None),
+            Value::Number(x) if x.fract() == 0.0 => Ok(Some(*x as i64)),
+            Value::Number(x) => Err(MemoryError::NonIntegerIndex(*x)),
+            _ => Err(MemoryError::CannotIndexWith(self.to_string())),
+        };
+
+        let step = part(step)?.unwrap_or(1);
+        if step == 0 {
+            Err(MemoryError::ZeroSliceStep)?
+        }
+
+        // Bounds count back from the end when negative, then are clamped to the items, where
+        // -1 stands for the position before the first item when stepping backwards
+        let (lowest, highest) = if step > 0 { (0, len) } else { (-1, len - 1) };
+        let clamp = |bound: Option<i64>, default| match bound {
+            None => default,
+            Some(x) if x < 0 => (x + len).max(lowest),
+            Some(x) => x.min(highest),
+        };
+        let (start, end) = if step > 0 {
+            (clamp(part(start)?, 0), clamp(part(end)?, len))
+        } else {
+            (clamp(part(start)?, len - 1), clamp(part(end)?, -1))
+        };
+
+        let mut positions = vec![];
+        let mut i = start;
+        while (step > 0 && i < end) || (step < 0 && i > end) {
+            positions.push(i as usize);
+            i += step;
+        }
+
+        self.pick(positions.into_iter())
+    }
+
+    /// A new List or String of the items at `positions`, skipping any that fall outside it
+    fn pick(&self, positions: impl Iterator<Item = usize>) -> Result<Value, MemoryError> {
+        if let Value::String(s) = self {
+            let chars: Vec<_> = s.chars().collect();
+            let slice: String = positions.filter_map(|i| chars.get(i)).collect();
+            return Ok(Value::String(boxed!(slice)));
+        }
+
+        if let Value::Object(obj) = self {
+            if let Object::List(ref list) = *obj.borrow() {
+                let slice = positions.filter_map(|i| list.get(i).cloned()).collect();
+                return Ok(Value::Object(Object::list(slice)));
+            }
+        }
+
+        Err(MemoryError::CannotIndex(self.to_string()))
+    }
+
+    /// The number of items a List or String can be indexed by
+    fn indexable_len(&self) -> Option<usize> {
+        match self {
+            Value::String(s) => Some(s.chars().count()),
+            Value::Object(obj) => match *obj.borrow() {
+                Object::List(ref list) => Some(list.len()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Membership test for `item in self`
+    pub fn contains(&self, item: &Value) -> Result<Value, ParseError> {
+        let found = match (self, item) {
+            (Value::String(s), Value::String(sub)) => s.contains(sub.as_str()),
+            (Value::Object(obj), _) => match (&*obj.borrow(), item) {
+                (Object::List(list), _) => list.contains(item),
+                (Object::Range(range), Value::Number(x)) => range.contains(*x),
+                (Object::Range(_), _) => false,
+                (Object::Map(map), _) => Key::try_from(item).is_ok_and(|key| map.contains(&key)),
+                (Object::Fnc(_) | Object::Record(_) | Object::Enum(_) | Object::Variant(_), _) => {
+                    Err(ParseError::IllegalBinaryOperation(
+                        item.to_string(),
+                        self.to_string(),
+                        Binary::Membership,
+                    ))?
+                }
+            },
+            _ => Err(ParseError::IllegalBinaryOperation(
+                item.to_string(),
+                self.to_string(),
+                Binary::Membership,
+            ))?,
+        };
+
+        Ok(Value::Boolean(found))
+    }
+
+    /// Writes `self[idx]`. Writing just past the end of a List appends to it
+    pub fn set_index(&mut self, idx: &Value, value: Value) -> Result<(), MemoryError> {
+        // Worked out up front, since the target can't be shown while it is borrowed mutably
+        let number = match idx {
+            Value::Number(x) => Some(*x),
+            _ => None,
+        };
+        let key = Key::try_from(idx);
+
+        if let Value::Object(obj) = self {
+            match (&mut *obj.borrow_mut(), number) {
+                (Object::List(list), Some(idx)) => {
+                    if idx == list.len() as f64 {
+                        list.push(value);
+                        return Ok(());
+                    }
+                    let idx = position(idx, list.len())?;
+                    list[idx] = value;
+                    return Ok(());
+                }
+                (Object::Map(map), _) => {
+                    map.insert(key?, value);
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+
+        if Type::of(self) == Type::List {
+            Err(MemoryError::CannotIndexWith(self.to_string()))?
+        }
+        Err(MemoryError::CannotIndex(self.to_string()))
+    }
+
+    /// Applies one of the arithmetic operators, which are the ones compound assignment supports
+    pub fn arithmetic(self, rhs: Value, op: Binary) -> Result<Value, ParseError> {
+        match op {
+            Binary::Add => self + rhs,
+            Binary::Subtract => self - rhs,
+            Binary::Multiply => self * rhs,
+            Binary::Divide => self / rhs,
+            Binary::Modulo => self % rhs,
+            _ => Err(ParseError::IllegalBinaryOperation(
+                self.to_string(),
+                rhs.to_string(),
+                op,
+            )),
+        }
+    }
+
+    pub fn compare(&self, rhs: &Value, op: Binary) -> Result<Value, ParseError> {
+        let (Value::Number(a), Value::Number(b)) = (self, rhs) else {
+            Err(ParseError::IllegalBinaryOperation(
+                self.to_string(),
+                rhs.to_string(),
+                op,
+            ))?
+        };
+
+        let result = match op {
+            Binary::GreaterThan => a > b,
+            Binary::GreaterOrEqual => a >= b,
+            Binary::LessThan => a < b,
+            Binary::LessOrEqual => a <= b,
+
+            _ => Err(ParseError::IllegalBinaryOperation(
+                self.to_string(),
+                rhs.to_string(),
+                op,
+            ))?,
+        };
+
+        Ok(Value::Boolean(result))
+    }
+}
+
+/// Resolves an index into one of `len` items, where negative indexes count back from the end
+fn position(idx: f64, len: usize) -> Result<usize, MemoryError> {
+    if idx.fract() != 0.0 {
+        Err(MemoryError::NonIntegerIndex(idx))?
+    }
+
+    let position = if idx < 0.0 { idx + len as f64 } else { idx };
+    if position < 0.0 || position >= len as f64 {
+        Err(MemoryError::IndexOutOfBounds { index: idx, len })?
+    }
+
+    Ok(position as usize)
+}
+
+thread_local! {
+    static DISPLAYING: RefCell<Vec<*const RefCell<Object>>> = const { RefCell::new(vec![]) };
+    static COMPARING: RefCell<Vec<(*const RefCell<Object>, *const RefCell<Object>)>> =
+        const { RefCell::new(vec![]) };
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Empty, Value::Empty) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => {
+                if Rc::ptr_eq(a, b) {
+                    return true;
+                }
+
+                // Objects that lead back to a pair already being compared are taken to be equal
+                let pair = (Rc::as_ptr(a), Rc::as_ptr(b));
+                if COMPARING.with_borrow(|pairs| pairs.contains(&pair)) {
+                    return true;
+                }
+
+                COMPARING.with_borrow_mut(|pairs| pairs.push(pair));
+                let equal = *a.borrow() == *b.borrow();
+                COMPARING.with_borrow_mut(|pairs| pairs.pop());
+                equal
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Display for Value {
@@ -60,10 +441,16 @@ impl Display for Value {
             Value::String(string) => write_val!(string),
 
             Value::Object(x) => {
-                // SAFETY
-                // TODO: Determine enforcement of the invariants or find a safe alternative
-                let repr = unsafe { x.as_ptr().as_ref().unwrap() };
-                write!(f, "{}", repr)
+                // A list may contain itself, which is shown as `[...]` instead of recursing
+                let ptr = Rc::as_ptr(x);
+                if DISPLAYING.with_borrow(|shown| shown.contains(&ptr)) {
+                    return write!(f, "[...]");
+                }
+
+                DISPLAYING.with_borrow_mut(|shown| shown.push(ptr));
+                let result = write!(f, "{}", x.borrow());
+                DISPLAYING.with_borrow_mut(|shown| shown.pop());
+                result
             }
         }
     }
@@ -77,7 +464,7 @@ impl From<bool> for Value {
 
 impl From<&str> for Value {
     fn from(value: &str) -> Self {
-        Value::String(boxed!(unescape(value)))
+        Value::String(boxed!(value.to_string()))
     }
 }
 
@@ -89,8 +476,7 @@ impl From<&String> for Value {
 
 impl From<char> for Value {
     fn from(value: char) -> Self {
-        let mut tmp = [0_u8; 1];
-        Value::from(&*value.encode_utf8(&mut tmp))
+        Value::String(boxed!(value.to_string()))
     }
 }
 